@@ -0,0 +1,128 @@
+use connection::Connection;
+use error::Error;
+use reql::r::r;
+use reql::results::{DbConfig, DbCreated, DbDropped, Rebalanced, Reconfigured, Synced, TableConfig, TableCreated, TableDropped, TableStatus, Waited};
+
+/// The optargs of `Connection::table_create`.  Whatever isn't set is left to the server.
+#[derive(Clone)]
+pub struct TableOptions {
+    shards: Option<u32>,
+    replicas: Option<u32>,
+    primary_key: Option<String>,
+    durability: Option<String>,
+}
+
+impl TableOptions {
+    pub fn new() -> TableOptions {
+        TableOptions {
+            shards: None,
+            replicas: None,
+            primary_key: None,
+            durability: None,
+        }
+    }
+
+    pub fn shards(mut self, shards: u32) -> TableOptions {
+        self.shards = Some(shards);
+        self
+    }
+
+    pub fn replicas(mut self, replicas: u32) -> TableOptions {
+        self.replicas = Some(replicas);
+        self
+    }
+
+    /// The field documents are keyed by, `id` unless set.
+    pub fn primary_key(mut self, primary_key: &str) -> TableOptions {
+        self.primary_key = Some(primary_key.to_owned());
+        self
+    }
+
+    /// `hard` or `soft`, for every write to the table.
+    pub fn durability(mut self, durability: &str) -> TableOptions {
+        self.durability = Some(durability.to_owned());
+        self
+    }
+}
+
+impl Default for TableOptions {
+    fn default() -> TableOptions {
+        TableOptions::new()
+    }
+}
+
+/// Administration, the way the provisioning scripts of the other drivers go about it.  Tables live
+/// in the connection's default database; queries built with `r` and sent with `run` reach the other
+/// ones, and decode into the same result types.
+impl Connection {
+    pub fn db_create(&self, name: &str) -> Result<DbCreated, Error> {
+        self.run(r.db_create(name))
+    }
+
+    /// Drops the database and every table in it.
+    pub fn db_drop(&self, name: &str) -> Result<DbDropped, Error> {
+        self.run(r.db_drop(name))
+    }
+
+    pub fn db_list(&self) -> Result<Vec<String>, Error> {
+        self.run(r.db_list())
+    }
+
+    pub fn db_config(&self, name: &str) -> Result<DbConfig, Error> {
+        self.run(r.db(name).config())
+    }
+
+    pub fn table_create(&self, name: &str, options: TableOptions) -> Result<TableCreated, Error> {
+        let mut query = r.table_create(name);
+        if let Some(shards) = options.shards {
+            query = query.shards(shards);
+        }
+        if let Some(replicas) = options.replicas {
+            query = query.replicas(replicas);
+        }
+        if let Some(ref primary_key) = options.primary_key {
+            query = query.primary_key(primary_key);
+        }
+        if let Some(ref durability) = options.durability {
+            query = query.durability(durability);
+        }
+
+        self.run(query)
+    }
+
+    pub fn table_drop(&self, name: &str) -> Result<TableDropped, Error> {
+        self.run(r.table_drop(name))
+    }
+
+    pub fn table_list(&self) -> Result<Vec<String>, Error> {
+        self.run(r.table_list())
+    }
+
+    pub fn table_config(&self, name: &str) -> Result<TableConfig, Error> {
+        self.run(r.table(name).config())
+    }
+
+    pub fn table_status(&self, name: &str) -> Result<TableStatus, Error> {
+        self.run(r.table(name).status())
+    }
+
+    /// Waits for every replica of the table to be ready.
+    pub fn table_wait(&self, name: &str) -> Result<Waited, Error> {
+        self.run(r.table(name).wait())
+    }
+
+    /// Spreads the table over `shards` shards, each kept on `replicas` servers.
+    pub fn reconfigure(&self, table: &str, shards: u32, replicas: u32) -> Result<Reconfigured, Error> {
+        self.run(r.table(table).reconfigure().shards(shards).replicas(replicas))
+    }
+
+    /// Moves the split points of the table's shards so they hold about as many documents each.
+    pub fn rebalance(&self, table: &str) -> Result<Rebalanced, Error> {
+        self.run(r.table(table).rebalance())
+    }
+
+    /// Makes sure every write to the table so far is on disk, even soft durability ones.
+    pub fn sync(&self, table: &str) -> Result<Synced, Error> {
+        self.run(r.table(table).sync())
+    }
+}
@@ -0,0 +1,466 @@
+use async_cursor::AsyncCursor;
+use backoff::Backoff;
+use connection::ConnectOptions;
+use dispatch::Dispatch;
+use error::Error;
+use futures::{future, stream, Future, Stream};
+use futures::future::Loop;
+use futures::sync::{mpsc, oneshot};
+use protocol::{self, QueryResponse};
+use ql2::VersionDummy_Version;
+use reql::query::Query;
+use reql::results::WriteResult;
+use reql::tree::IntoTree;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::fmt::Display;
+use std::io::BufReader;
+use std::net::ToSocketAddrs;
+use std::str;
+use std::net;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio;
+use tokio::io::{self, AsyncRead, ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+use tokio::prelude::FutureExt;
+use tokio::reactor::Handle;
+
+/// A boxed future resolving to `T`, which is what every async call on the connection returns.
+pub type ReqlFuture<'a, T> = Box<dyn Future<Item = T, Error = Error> + Send + 'a>;
+
+/// The async counterpart of `Connection`, for use on a tokio runtime.
+///
+/// Once the handshake is done, the socket is split in two tasks: one writes the frames queued by
+/// queries, the other reads responses and resolves whichever query is waiting on the token.  A
+/// connection is cheap to clone; clones share the same socket.
+///
+/// Everything in `ConnectOptions` applies except `write_timeout`, since writes happen on their own
+/// task, and TLS, which isn't supported here yet.
+///
+/// When reconnecting, the handshake runs on a thread of its own, with the blocking code
+/// `Connection` uses: the SCRAM state borrows the user and password, which a future that outlives
+/// the call that made it can't do.
+#[derive(Clone)]
+pub struct AsyncConnection {
+    options: ConnectOptions,
+    // The queue of the task writing to the current socket.  Replaced when the connection is
+    // re-established.
+    frames: Arc<Mutex<mpsc::UnboundedSender<Vec<u8>>>>,
+    dispatch: Dispatch<oneshot::Sender<Result<QueryResponse, Error>>>,
+}
+
+fn server_error<E: Display>(error: E) -> Error {
+    Error::ServerError(format!("{}", error))
+}
+
+/// Reads a NULL-terminated handshake message, and returns it without the NULL byte.
+fn read_until_null(stream: TcpStream) -> ReqlFuture<'static, (TcpStream, String)> {
+    Box::new(io::read_until(BufReader::new(stream), 0, vec![])
+        .map_err(server_error)
+        .and_then(|(reader, mut recv)| {
+            let _ = recv.pop();
+            let resp = my_try!(String::from_utf8(recv));
+
+            Ok((reader.into_inner(), resp))
+        }))
+}
+
+fn write_message(stream: TcpStream, message: Vec<u8>) -> ReqlFuture<'static, TcpStream> {
+    Box::new(io::write_all(stream, message)
+        .map_err(server_error)
+        .map(|(stream, _)| stream))
+}
+
+/// Uses the handshake for V0_3 and V0_4: everything is sent at once, and the server answers with a
+/// single NULL-terminated string.
+fn legacy_handshake(stream: TcpStream, version: VersionDummy_Version, auth_key: &str) -> ReqlFuture<'static, TcpStream> {
+    let message = match protocol::legacy_handshake_message(version, auth_key) {
+        Ok(message) => message,
+        Err(error) => return Box::new(future::err(error)),
+    };
+
+    Box::new(write_message(stream, message)
+        .and_then(read_until_null)
+        .and_then(|(stream, resp)| protocol::parse_legacy_response(&resp).map(|_| stream)))
+}
+
+/// Uses the handshake for V1_0, defined in https://rethinkdb.com/docs/writing-drivers/.  The SCRAM
+/// state borrows `user` and `password`, so the handshake can't outlive them.
+fn handshake<'a>(stream: TcpStream, user: &'a str, password: &'a str) -> ReqlFuture<'a, TcpStream> {
+    let (server_first, client_first) = match protocol::client_first_message(user, password) {
+        Ok(message) => message,
+        Err(error) => return Box::new(future::err(error)),
+    };
+
+    Box::new(write_message(stream, protocol::version_number(VersionDummy_Version::V1_0 as u32))
+        .and_then(read_until_null)
+        .and_then(|(stream, resp)| protocol::parse_protocol_response(&resp).map(|_| stream))
+        .and_then(move |stream| write_message(stream, client_first))
+        .and_then(read_until_null)
+        .and_then(move |(stream, resp)| {
+            let response = protocol::parse_server_message(&resp)?;
+            let client_final = my_try!(server_first.handle_server_first(&response.authentication));
+            let (server_final, client_final) = protocol::client_final_message(client_final)?;
+
+            Ok(write_message(stream, client_final).map(move |stream| (stream, server_final)))
+        })
+        .flatten()
+        .and_then(|(stream, server_final)| {
+            read_until_null(stream).map(move |(stream, resp)| (stream, resp, server_final))
+        })
+        .and_then(|(stream, resp, server_final)| {
+            let response = protocol::parse_server_message(&resp)?;
+            my_try!(server_final.handle_server_final(&response.authentication));
+
+            Ok(stream)
+        }))
+}
+
+impl AsyncConnection {
+    /// Connects to the provided server `host` and `port`.  Must be polled from within a tokio
+    /// runtime, since the connection spawns its reader and writer tasks on it.
+    pub fn connect<'a>(host: &str, port: u16, user: &'a str, password: &'a str) -> ReqlFuture<'a, AsyncConnection> {
+        AsyncConnection::establish(ConnectOptions::new(host, port), user, password)
+    }
+
+    /// Connects with the socket options, timeouts and query defaults in `options`.  The hosts are
+    /// tried in order, and the first one to complete the handshake is used.
+    pub fn connect_with<'a>(options: &'a ConnectOptions) -> ReqlFuture<'a, AsyncConnection> {
+        AsyncConnection::establish(options.clone(), &options.user, &options.password)
+    }
+
+    fn establish<'a>(options: ConnectOptions, user: &'a str, password: &'a str) -> ReqlFuture<'a, AsyncConnection> {
+        let hosts = options.hosts().into_iter();
+        let last_error = Error::ServerError("There are no hosts to connect to.".to_owned());
+
+        Box::new(future::loop_fn((hosts, last_error), move |(mut hosts, last_error)| -> ReqlFuture<'a, Loop<AsyncConnection, _>> {
+            let (host, port) = match hosts.next() {
+                Some(host) => host,
+                None => return Box::new(future::err(last_error)),
+            };
+
+            Box::new(AsyncConnection::negotiate(options.clone(), host, port, user, password).then(move |result| match result {
+                Ok(conn) => Ok(Loop::Break(conn)),
+                Err(error) => Ok(Loop::Continue((hosts, error))),
+            }))
+        }))
+    }
+
+    fn negotiate<'a>(options: ConnectOptions, host: String, port: u16, user: &'a str, password: &'a str) -> ReqlFuture<'a, AsyncConnection> {
+        let handshake = options.handshake;
+        let retry = options.clone();
+        let retry_host = host.clone();
+
+        Box::new(AsyncConnection::open(options, handshake.first_attempt(), &host, port, user, password)
+            .or_else(move |error| -> ReqlFuture<'a, AsyncConnection> {
+                // Servers that turn down a handshake version close the connection, so the next one
+                // has to be tried on a new one.
+                match handshake.fallback(&error) {
+                    Some(version) => AsyncConnection::open(retry, version, &retry_host, port, user, password),
+                    None => Box::new(future::err(error)),
+                }
+            }))
+    }
+
+    fn open<'a>(options: ConnectOptions, version: VersionDummy_Version, host: &str, port: u16, user: &'a str, password: &'a str) -> ReqlFuture<'a, AsyncConnection> {
+        #[cfg(feature = "tls")]
+        {
+            if options.tls.is_some() {
+                return Box::new(future::err(Error::ServerError("TLS is not supported by AsyncConnection.".to_owned())));
+            }
+        }
+        let addr = match (host, port).to_socket_addrs().map(|mut addrs| addrs.next()) {
+            Ok(Some(addr)) => addr,
+            Ok(None) => return Box::new(future::err(Error::ServerError(format!("Could not resolve {}:{}", host, port)))),
+            Err(error) => return Box::new(future::err(server_error(error))),
+        };
+
+        let connected: ReqlFuture<'static, TcpStream> = match options.connect_timeout {
+            Some(timeout) => Box::new(TcpStream::connect(&addr)
+                .timeout(timeout)
+                .map_err(move |error| if error.is_elapsed() {
+                    Error::ServerError(format!("Timed out after {:?} connecting to {}.", timeout, addr))
+                } else {
+                    server_error(error)
+                })),
+            None => Box::new(TcpStream::connect(&addr).map_err(server_error)),
+        };
+        let nodelay = options.nodelay;
+        let keepalive = options.keepalive;
+        let auth_key = options.auth_key.clone();
+
+        Box::new(connected
+            .and_then(move |stream| {
+                my_try!(stream.set_nodelay(nodelay));
+                my_try!(stream.set_keepalive(keepalive));
+
+                Ok(stream)
+            })
+            .and_then(move |stream| match version {
+                VersionDummy_Version::V1_0 => handshake(stream, user, password),
+                _ => legacy_handshake(stream, version, &auth_key),
+            })
+            .map(move |stream| AsyncConnection::start(options, stream)))
+    }
+
+    fn start(options: ConnectOptions, stream: TcpStream) -> AsyncConnection {
+        let dispatch = Dispatch::new();
+        let frames = AsyncConnection::spawn_tasks(stream, dispatch.clone());
+
+        AsyncConnection {
+            options: options,
+            frames: Arc::new(Mutex::new(frames)),
+            dispatch: dispatch,
+        }
+    }
+
+    /// Starts the tasks writing to and reading from `stream`, and returns the queue of the writer.
+    fn spawn_tasks(stream: TcpStream, dispatch: Dispatch<oneshot::Sender<Result<QueryResponse, Error>>>) -> mpsc::UnboundedSender<Vec<u8>> {
+        let (reader, writer) = stream.split();
+        let (frames, queued) = mpsc::unbounded();
+
+        tokio::spawn(AsyncConnection::write_frames(writer, queued));
+        tokio::spawn(AsyncConnection::route_responses(reader, dispatch));
+
+        frames
+    }
+
+    /// Makes sure there's a working socket to send a new query on, reconnecting if the options
+    /// allow it.
+    fn ensure_open(&self) -> ReqlFuture<'static, ()> {
+        let (reason, backoff) = match self.dispatch.reconnect_with(&self.options.reconnect) {
+            Ok(Some(reconnect)) => reconnect,
+            Ok(None) => return Box::new(future::ok(())),
+            Err(error) => return Box::new(future::err(error)),
+        };
+        let conn = self.clone();
+
+        Box::new(AsyncConnection::reopen(self.options.clone(), backoff, reason)
+            .and_then(move |stream| conn.relink(stream)))
+    }
+
+    /// Opens a new socket and runs the handshake on it, on a thread of its own, trying the hosts
+    /// the way `Connection` does when it reconnects.
+    fn reopen(options: ConnectOptions, backoff: Backoff, reason: String) -> ReqlFuture<'static, TcpStream> {
+        let (sender, receiver) = oneshot::channel::<Result<net::TcpStream, Error>>();
+        let spawned = thread::Builder::new()
+            .name("rethinkdb-reconnect".to_owned())
+            .spawn(move || {
+                let _ = sender.send(options.retry_hosts(&backoff, reason, |host, port| options.open_handshaken(host, port)));
+            });
+        if let Err(error) = spawned {
+            return Box::new(future::err(server_error(error)));
+        }
+
+        Box::new(receiver
+            .map_err(server_error)
+            .and_then(|opened| opened)
+            .and_then(|stream| TcpStream::from_std(stream, &Handle::default()).map_err(server_error)))
+    }
+
+    /// Replaces the broken socket with `stream`, unless another query got there first.
+    fn relink(&self, stream: TcpStream) -> Result<(), Error> {
+        self.dispatch.reopen(|| {
+            // Replacing the queue drops the old one, which shuts the old socket down.
+            *my_try!(self.frames.lock()) = AsyncConnection::spawn_tasks(stream, self.dispatch.clone());
+
+            Ok(())
+        })
+    }
+
+    pub fn options(&self) -> &ConnectOptions {
+        &self.options
+    }
+
+    /// Writes queued frames in order.  When every clone of the connection is gone, the socket is
+    /// shut down, which in turn stops the reader task.
+    fn write_frames(writer: WriteHalf<TcpStream>, queued: mpsc::UnboundedReceiver<Vec<u8>>) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+        Box::new(queued
+            .fold(writer, |writer, frame| {
+                io::write_all(writer, frame).map(|(writer, _)| writer).map_err(|_| ())
+            })
+            .and_then(|writer| io::shutdown(writer).map_err(|_| ()))
+            .map(|_| ()))
+    }
+
+    /// Reads every response off the socket and hands it to `dispatch`, until the socket fails.
+    fn route_responses(reader: ReadHalf<TcpStream>, dispatch: Dispatch<oneshot::Sender<Result<QueryResponse, Error>>>) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+        let frames = stream::unfold(reader, |reader| {
+            let frame = io::read_exact(reader, [0; protocol::HEADER_LEN])
+                .and_then(|(reader, header)| {
+                    let (token, len) = protocol::decode_header(&header);
+
+                    io::read_exact(reader, vec![0; len as usize])
+                        .map(move |(reader, recv)| ((token, recv), reader))
+                });
+
+            Some(frame)
+        });
+        let routed = dispatch.clone();
+
+        Box::new(frames
+            .map_err(server_error)
+            .for_each(move |(token, recv)| routed.route(token, &recv))
+            .then(move |result| {
+                let reason = match result {
+                    Ok(()) => "The connection was closed.".to_owned(),
+                    Err(error) => format!("{}", error),
+                };
+                dispatch.close(reason);
+
+                Ok(())
+            }))
+    }
+
+    /// Queues `query` to be written without waiting for the response.
+    pub fn send_query(&self, token: u64, query: &Query) -> Result<(), Error> {
+        let frame = protocol::encode_query(token, query, protocol::MAX_QUERY_LEN)?;
+
+        my_try!(my_try!(self.frames.lock()).unbounded_send(frame));
+
+        Ok(())
+    }
+
+    /// Sends `query` under `token`, and resolves to the server's answer if it comes within the read
+    /// timeout.
+    fn send_and_receive(&self, token: u64, query: &Query) -> ReqlFuture<'static, QueryResponse> {
+        let timeout = self.options.read_timeout;
+
+        self.send_and_wait(token, query, timeout)
+    }
+
+    /// Sends `query` under `token`, and resolves to the server's answer if it comes within
+    /// `timeout`.
+    fn send_and_wait(&self, token: u64, query: &Query, timeout: Option<Duration>) -> ReqlFuture<'static, QueryResponse> {
+        let (sender, receiver) = oneshot::channel();
+        if let Err(error) = self.dispatch.send(token, sender, || self.send_query(token, query)) {
+            return Box::new(future::err(error));
+        }
+
+        let received = receiver.map_err(server_error).and_then(|response| response);
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => return Box::new(received),
+        };
+        let dispatch = self.dispatch.clone();
+
+        Box::new(received
+            .timeout(timeout)
+            .map_err(move |error| {
+                if error.is_elapsed() {
+                    dispatch.forget(token);
+
+                    return Error::ResponseTimeout(timeout);
+                }
+
+                match error.into_inner() {
+                    Some(error) => error,
+                    None => Error::ServerError("The timer failed.".to_owned()),
+                }
+            }))
+    }
+
+    /// Sends a query that starts something new on the server, under a new token.  This is where a
+    /// broken connection gets replaced.
+    fn send_new_query(&self, query: &Query) -> ReqlFuture<'static, QueryResponse> {
+        let conn = self.clone();
+        let query = query.clone();
+
+        Box::new(self.ensure_open().and_then(move |_| match conn.dispatch.next_token() {
+            Ok(token) => conn.send_and_receive(token, &query),
+            Err(error) => Box::new(future::err(error)),
+        }))
+    }
+
+    /// Sends `query` as a new START query and resolves to the first response as is.  Its token is
+    /// the one to pass to `continue_query` and `stop_query`.
+    pub fn start_query<T: IntoTree>(&self, query: T) -> ReqlFuture<'static, QueryResponse> {
+        let tree = match query.into_tree() {
+            Ok(tree) => tree,
+            Err(error) => return Box::new(future::err(error)),
+        };
+        let replay = self.options.replay_reads && tree.is_read_only();
+
+        let mut query = Query::start(tree);
+        query.global_optargs = self.options.query_optargs();
+
+        let conn = self.clone();
+        Box::new(self.send_new_query(&query).or_else(move |error| -> ReqlFuture<'static, QueryResponse> {
+            if replay && error.is_retryable() {
+                return conn.send_new_query(&query);
+            }
+
+            Box::new(future::err(error))
+        }))
+    }
+
+    /// Runs `query` and resolves to its decoded result: the value for queries returning one, or
+    /// every element for sequences, fetching the remaining batches first.  Changefeeds never end,
+    /// so they have to go through `run_cursor`.
+    pub fn run<T, Q>(&self, query: Q) -> ReqlFuture<'static, T>
+        where T: DeserializeOwned + Send + 'static,
+              Q: IntoTree
+    {
+        let conn = self.clone();
+
+        Box::new(self.start_query(query).and_then(move |response| -> ReqlFuture<'static, T> {
+            if !response.response.is_partial() {
+                let result = response.response.into_value().and_then(|json| conn.options.decode(json));
+                return Box::new(future::result(result));
+            }
+
+            let options = conn.options.clone();
+            match AsyncCursor::<Value>::new(conn, response) {
+                Ok(cursor) => Box::new(cursor.collect().and_then(move |results| options.decode(Value::Array(results)))),
+                Err(error) => Box::new(future::err(error)),
+            }
+        }))
+    }
+
+    /// Runs a write and resolves to its result, failing if any document couldn't be written.
+    pub fn write<T, Q>(&self, query: Q) -> ReqlFuture<'static, WriteResult<T>>
+        where T: DeserializeOwned + Send + 'static,
+              Q: IntoTree
+    {
+        Box::new(self.run::<Value, Q>(query).and_then(WriteResult::checked))
+    }
+
+    /// Runs `query` and resolves to a stream of its results, which fetches the remaining batches
+    /// from the server as it is polled.
+    pub fn run_cursor<T, Q>(&self, query: Q) -> ReqlFuture<'static, AsyncCursor<T>>
+        where T: DeserializeOwned + Send + 'static,
+              Q: IntoTree
+    {
+        let conn = self.clone();
+
+        Box::new(self.start_query(query).and_then(move |response| AsyncCursor::new(conn, response)))
+    }
+
+    /// Fetches the next batch of the query started under `token`.
+    pub fn continue_query(&self, token: u64) -> ReqlFuture<'static, QueryResponse> {
+        self.send_and_receive(token, &Query::continue_query())
+    }
+
+    /// Like `continue_query`, for changefeeds: the server only answers once there are changes, so
+    /// the read timeout doesn't apply.
+    pub fn continue_feed(&self, token: u64) -> ReqlFuture<'static, QueryResponse> {
+        self.send_and_wait(token, &Query::continue_query(), None)
+    }
+
+    /// Stops the query started under `token`, so the server stops producing batches for it.
+    pub fn stop_query(&self, token: u64) -> ReqlFuture<'static, QueryResponse> {
+        self.send_and_receive(token, &Query::stop())
+    }
+
+    /// Waits until the server has processed every noreply query sent on this connection.
+    pub fn noreply_wait(&self) -> ReqlFuture<'static, QueryResponse> {
+        self.send_new_query(&Query::noreply_wait())
+    }
+
+    /// Asks the server for its id, name and proxy status.
+    pub fn server_info(&self) -> ReqlFuture<'static, QueryResponse> {
+        self.send_new_query(&Query::server_info())
+    }
+}
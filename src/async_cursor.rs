@@ -0,0 +1,114 @@
+use async_connection::{AsyncConnection, ReqlFuture};
+use error::Error;
+use futures::{Async, Future, Poll, Stream};
+use protocol::QueryResponse;
+use reql::response::FeedType;
+use reql::query::Query;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+
+/// The async counterpart of `Cursor`: a stream of results that sends CONTINUE on the original
+/// token whenever its buffer drains and the last batch was a SUCCESS_PARTIAL.  Each result is
+/// decoded into a `T` as it is yielded.
+pub struct AsyncCursor<T = Value> {
+    conn: AsyncConnection,
+    token: u64,
+    buffer: VecDeque<Value>,
+    feed: Option<FeedType>,
+    // Whether the server has sent its last batch for this token.
+    done: bool,
+    // The CONTINUE query in flight, if any.
+    pending: Option<ReqlFuture<'static, QueryResponse>>,
+    results: PhantomData<T>,
+}
+
+impl<T: DeserializeOwned> AsyncCursor<T> {
+    /// Builds a cursor from the first response to a START query.
+    pub fn new(conn: AsyncConnection, response: QueryResponse) -> Result<AsyncCursor<T>, Error> {
+        let mut cursor = AsyncCursor {
+            conn: conn,
+            token: response.query_token,
+            buffer: VecDeque::new(),
+            feed: response.response.feed_type(),
+            done: false,
+            pending: None,
+            results: PhantomData,
+        };
+        cursor.load(response)?;
+
+        Ok(cursor)
+    }
+
+    pub fn token(&self) -> u64 {
+        self.token
+    }
+
+    /// The kind of changefeed the cursor follows, or `None` for plain results.  Changefeeds only
+    /// end when the cursor is dropped, or with an error.
+    pub fn feed_type(&self) -> Option<FeedType> {
+        self.feed
+    }
+
+    /// Buffers the results of a batch.
+    fn load(&mut self, response: QueryResponse) -> Result<(), Error> {
+        self.done = !response.response.is_partial();
+        let batch = response.response.into_batch()?;
+        self.buffer.extend(batch);
+
+        Ok(())
+    }
+}
+
+impl<T: DeserializeOwned> Stream for AsyncCursor<T> {
+    type Item = T;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<T>, Error> {
+        // Batches can legitimately be empty, so keep asking until we get a result or the server
+        // says it is finished.
+        loop {
+            if let Some(result) = self.buffer.pop_front() {
+                return self.conn.options().decode(result).map(|result| Async::Ready(Some(result)));
+            }
+            if self.pending.is_none() {
+                if self.done {
+                    return Ok(Async::Ready(None));
+                }
+                self.pending = Some(match self.feed {
+                    Some(_) => self.conn.continue_feed(self.token),
+                    None => self.conn.continue_query(self.token),
+                });
+            }
+
+            let polled = match self.pending {
+                Some(ref mut pending) => pending.poll(),
+                None => continue,
+            };
+            let loaded = match polled {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(response)) => self.load(response),
+                Err(error) => Err(error),
+            };
+            self.pending = None;
+            if let Err(error) = loaded {
+                // Either the server ended the query with an error, or the connection is no longer
+                // usable.  There's nothing more to read in both cases.
+                self.done = true;
+
+                return Err(error);
+            }
+        }
+    }
+}
+
+impl<T> Drop for AsyncCursor<T> {
+    /// Dropping a cursor before it is exhausted tells the server to stop the query.  Nobody waits
+    /// for the answer; the connection discards it.
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.conn.send_query(self.token, &Query::stop());
+        }
+    }
+}
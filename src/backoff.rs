@@ -0,0 +1,79 @@
+use std::cmp;
+use std::time::Duration;
+
+/// How long to wait between attempts to reconnect a connection whose socket broke.  The delay
+/// doubles after every round of attempts over the seed hosts, up to `max_delay`.
+#[derive(Clone)]
+pub struct Backoff {
+    initial_delay: Duration,
+    max_delay: Duration,
+    max_retries: Option<usize>,
+}
+
+impl Backoff {
+    pub fn new() -> Backoff {
+        Backoff {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            max_retries: Some(10),
+        }
+    }
+
+    /// The wait before the first retry.
+    pub fn initial_delay(mut self, initial_delay: Duration) -> Backoff {
+        self.initial_delay = initial_delay;
+        self
+    }
+
+    /// The longest the delay can grow to.
+    pub fn max_delay(mut self, max_delay: Duration) -> Backoff {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// How many rounds to retry after the first one fails before giving up.  `None` retries
+    /// forever.
+    pub fn max_retries(mut self, max_retries: Option<usize>) -> Backoff {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// The delays to wait before each retry, in order.
+    pub fn delays(&self) -> Delays {
+        Delays {
+            backoff: self.clone(),
+            next: self.initial_delay,
+            retries: 0,
+        }
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Backoff {
+        Backoff::new()
+    }
+}
+
+pub struct Delays {
+    backoff: Backoff,
+    next: Duration,
+    retries: usize,
+}
+
+impl Iterator for Delays {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Duration> {
+        if let Some(max_retries) = self.backoff.max_retries {
+            if self.retries >= max_retries {
+                return None;
+            }
+        }
+
+        let delay = cmp::min(self.next, self.backoff.max_delay);
+        self.next = cmp::min(delay.checked_mul(2).unwrap_or(self.backoff.max_delay), self.backoff.max_delay);
+        self.retries += 1;
+
+        Some(delay)
+    }
+}
@@ -1,70 +1,391 @@
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use backoff::Backoff;
+use cursor::Cursor;
+use dispatch::Dispatch;
 use error::Error;
+use feed::Feed;
+use protocol::{self, HandshakeVersion, ProtocolSuccessResponse, QueryResponse, ServerSuccessResponse};
 use ql2::{Term_TermType, VersionDummy_Version};
-use reql::tree::Tree;
-use rustc_serialize::json::{self, Json, ToJson};
-use scram::{ClientFinal, ClientFirst, ServerFinal, ServerFirst};
-// NOTE: Think of this like an Atom in Clojure.  It allows local mutability.
-use std::cell::{Cell, Ref, RefCell};
+use reql::query::Query;
+use reql::response;
+use reql::results::{self, WriteResult};
+use reql::tree::{IntoTree, Tree};
+use reql::types::{self, Format};
+use net2::TcpStreamExt;
+use scram::{ClientFinal, ServerFinal, ServerFirst};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
 use std::collections::BTreeMap;
-use std::io::{BufRead, BufReader, Read, Write};
-use std::net::{Shutdown, TcpStream};
-use std::str;
-use std::u32;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+#[cfg(feature = "tls")]
+use tls::TlsOptions;
+use transport::Transport;
+
+/// Everything needed to open a connection, plus the defaults applied to every query sent on it.
+///
+/// ```rust,no_run
+/// # extern crate rethinkdb;
+/// # use rethinkdb::{ConnectOptions, Connection, Error};
+/// # use std::time::Duration;
+/// # fn connect() -> Result<Connection, Error> {
+/// let options = ConnectOptions::new("localhost", 28015)
+///     .user("admin")
+///     .db("app")
+///     .durability("soft")
+///     .connect_timeout(Duration::from_secs(5));
+/// let conn = Connection::connect_with(options)?;
+/// # Ok(conn)
+/// # }
+/// # fn main() {}
+/// ```
+#[derive(Clone)]
+pub struct ConnectOptions {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) seed_hosts: Vec<(String, u16)>,
+    pub(crate) reconnect: Option<Backoff>,
+    pub(crate) replay_reads: bool,
+    pub(crate) user: String,
+    pub(crate) password: String,
+    pub(crate) handshake: HandshakeVersion,
+    pub(crate) auth_key: String,
+    pub(crate) db: Option<String>,
+    pub(crate) connect_timeout: Option<Duration>,
+    pub(crate) read_timeout: Option<Duration>,
+    pub(crate) write_timeout: Option<Duration>,
+    pub(crate) keepalive: Option<Duration>,
+    pub(crate) nodelay: bool,
+    pub(crate) global_optargs: BTreeMap<String, Value>,
+    #[cfg(feature = "tls")]
+    pub(crate) tls: Option<TlsOptions>,
+}
 
-const SUB_PROTOCOL_VERSION: i64 = 0;
+impl ConnectOptions {
+    /// Connects as `admin`, with no password, to the `test` database, which is what the server
+    /// does when nothing else is specified.
+    pub fn new(host: &str, port: u16) -> ConnectOptions {
+        ConnectOptions {
+            host: host.to_owned(),
+            port: port,
+            seed_hosts: vec![],
+            reconnect: None,
+            replay_reads: false,
+            user: "admin".to_owned(),
+            password: "".to_owned(),
+            handshake: HandshakeVersion::V1_0,
+            auth_key: "".to_owned(),
+            db: None,
+            connect_timeout: None,
+            read_timeout: None,
+            write_timeout: None,
+            keepalive: None,
+            nodelay: true,
+            global_optargs: BTreeMap::new(),
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
 
-/// Represents a database connection.
-pub struct Connection {
-    pub host: String,
-    pub port: u16,
-    pub user: String,
-    pub password: String,
-    stream: RefCell<TcpStream>,
-    query_token: Cell<u64>,
-}
+    /// Another server of the cluster, tried in order after `host` when connecting or reconnecting.
+    pub fn seed_host(mut self, host: &str, port: u16) -> ConnectOptions {
+        self.seed_hosts.push((host.to_owned(), port));
+        self
+    }
 
-pub struct QueryResponse {
-    query_token: u64,
-    length: u32,
-    response: Json,
-}
+    /// Reconnects, waiting `backoff` between rounds of attempts over the hosts, when the socket
+    /// breaks.  Queries in flight at that point fail with `Error::ConnectionLost`, and so do
+    /// cursors opened before the reconnect.
+    pub fn reconnect(mut self, backoff: Backoff) -> ConnectOptions {
+        self.reconnect = Some(backoff);
+        self
+    }
+
+    /// Whether `run` sends a read-only query again, once, when the connection is lost before the
+    /// answer arrives.  Only useful along with `reconnect`.
+    pub fn replay_reads(mut self, replay_reads: bool) -> ConnectOptions {
+        self.replay_reads = replay_reads;
+        self
+    }
+
+    pub fn user(mut self, user: &str) -> ConnectOptions {
+        self.user = user.to_owned();
+        self
+    }
+
+    pub fn password(mut self, password: &str) -> ConnectOptions {
+        self.password = password.to_owned();
+        self
+    }
+
+    /// Which handshake to open connections with.  Servers older than 2.3 only speak V0_4 (or V0_3),
+    /// which authenticate with `auth_key` instead of the user and password.
+    pub fn handshake(mut self, handshake: HandshakeVersion) -> ConnectOptions {
+        self.handshake = handshake;
+        self
+    }
+
+    /// The auth key for the V0_3 and V0_4 handshakes.
+    pub fn auth_key(mut self, auth_key: &str) -> ConnectOptions {
+        self.auth_key = auth_key.to_owned();
+        self
+    }
+
+    /// The database used by queries that don't name one.
+    pub fn db(mut self, db: &str) -> ConnectOptions {
+        self.db = Some(db.to_owned());
+        self
+    }
+
+    /// How long to wait for the TCP connection to be established.
+    pub fn connect_timeout(mut self, timeout: Duration) -> ConnectOptions {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// How long to wait for each response, including each batch of a cursor.  Changefeeds can go
+    /// quiet for much longer than a normal query takes, so leave this unset on connections that
+    /// run them.
+    pub fn read_timeout(mut self, timeout: Duration) -> ConnectOptions {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// How long a query may take to be written to the socket.
+    pub fn write_timeout(mut self, timeout: Duration) -> ConnectOptions {
+        self.write_timeout = Some(timeout);
+        self
+    }
+
+    /// Turns on TCP keepalive, with probes starting after the connection has been idle this long.
+    pub fn keepalive(mut self, idle: Duration) -> ConnectOptions {
+        self.keepalive = Some(idle);
+        self
+    }
+
+    /// Whether to disable Nagle's algorithm.  On by default, since queries are small and latency
+    /// matters more than packet count.
+    pub fn nodelay(mut self, nodelay: bool) -> ConnectOptions {
+        self.nodelay = nodelay;
+        self
+    }
+
+    /// Sets a global optarg sent with every query, e.g. `profile` or `array_limit`.
+    pub fn global_optarg(mut self, name: &str, value: Value) -> ConnectOptions {
+        self.global_optargs.insert(name.to_owned(), value);
+        self
+    }
+
+    /// `hard` or `soft`: whether writes wait for the data to be written to disk.
+    pub fn durability(self, durability: &str) -> ConnectOptions {
+        self.global_optarg("durability", Value::from(durability))
+    }
+
+    /// `single`, `majority` or `outdated`: how up to date reads have to be.
+    pub fn read_mode(self, read_mode: &str) -> ConnectOptions {
+        self.global_optarg("read_mode", Value::from(read_mode))
+    }
+
+    /// How TIME values come back.
+    pub fn time_format(self, format: Format) -> ConnectOptions {
+        self.global_optarg("time_format", Value::from(format.as_str()))
+    }
+
+    /// How BINARY values come back.
+    pub fn binary_format(self, format: Format) -> ConnectOptions {
+        self.global_optarg("binary_format", Value::from(format.as_str()))
+    }
+
+    /// How the results of `group` come back: a list of groups to decode into `Grouped` or
+    /// `Vec<(K, V)>`, or the GROUPED_DATA pseudo-type.
+    pub fn group_format(self, format: Format) -> ConnectOptions {
+        self.global_optarg("group_format", Value::from(format.as_str()))
+    }
+
+    /// Talks to the server over TLS.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: TlsOptions) -> ConnectOptions {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// The global optargs for the envelope of every START query.  The default database goes in as
+    /// a DB term.
+    pub(crate) fn query_optargs(&self) -> BTreeMap<String, Tree> {
+        let mut optargs = self.global_optargs.iter()
+            .map(|(name, value)| (name.clone(), Tree::Datum(value.clone())))
+            .collect::<BTreeMap<String, Tree>>();
+        if let Some(ref db) = self.db {
+            optargs.insert("db".to_owned(), Tree::query(Term_TermType::DB, vec![Tree::Datum(Value::from(db.as_str()))]));
+        }
+
+        optargs
+    }
+
+    /// Decodes a result, after converting its pseudo-types as asked by the format options.  With
+    /// native groups, a result that doesn't decode from `{"group", "reduction"}` objects is tried
+    /// again as `[group, reduction]` pairs, so `Vec<(K, V)>` works as well as `Grouped<K, V>`.  Join
+    /// results are likewise tried again as `[left, right]` pairs, for `(L, R)` as well as `Joined`.
+    pub(crate) fn decode<T: DeserializeOwned>(&self, json: Value) -> Result<T, Error> {
+        let raw_groups = self.global_optargs.get("group_format") == Some(&Value::from(Format::Raw.as_str()));
+        if raw_groups || !types::has_groups(&json) {
+            if !results::has_joined(&json) {
+                return response::decode(json);
+            }
+
+            return response::decode(json.clone())
+                .or_else(|error| response::decode(results::joined_pairs(json)).map_err(|_| error));
+        }
+
+        response::decode(types::native_groups(json.clone()))
+            .or_else(|error| response::decode(types::group_pairs(json)).map_err(|_| error))
+    }
 
-/// The response returned by V1_0 of the RethinkDB handshake protocol, after a protocol version has
-/// been successfully set.
-#[derive(Debug,RustcDecodable)]
-struct ProtocolSuccessResponse {
-    success: bool,
-    min_protocol_version: i64,
-    max_protocol_version: i64,
-    server_version: String,
+    /// The hosts to try, in order.
+    pub(crate) fn hosts(&self) -> Vec<(String, u16)> {
+        let mut hosts = vec![(self.host.clone(), self.port)];
+        hosts.extend(self.seed_hosts.iter().cloned());
+
+        hosts
+    }
+
+    /// Calls `attempt` on each host in turn until one succeeds, waiting `backoff` between rounds.
+    /// Once it gives up, the error is a `ConnectionLost` with the last failure, or with `reason` if
+    /// there were no hosts to try.
+    pub(crate) fn retry_hosts<T, F>(&self, backoff: &Backoff, reason: String, mut attempt: F) -> Result<T, Error>
+        where F: FnMut(&str, u16) -> Result<T, Error>
+    {
+        let mut last_error = Error::ConnectionLost(reason);
+        let mut delays = backoff.delays();
+        loop {
+            for (host, port) in self.hosts() {
+                match attempt(&host, port) {
+                    Ok(result) => return Ok(result),
+                    Err(error) => last_error = error,
+                }
+            }
+
+            match delays.next() {
+                Some(delay) => thread::sleep(delay),
+                None => return Err(Error::ConnectionLost(format!("{}", last_error))),
+            }
+        }
+    }
+
+    /// Opens a plain TCP socket to `host` and runs the handshake on it, falling back to an older
+    /// version the way `Connection` does.  `AsyncConnection` takes the socket over from there.
+    #[cfg(feature = "async")]
+    pub(crate) fn open_handshaken(&self, host: &str, port: u16) -> Result<TcpStream, Error> {
+        match self.handshaken_socket(self.handshake.first_attempt(), host, port) {
+            Err(error) => match self.handshake.fallback(&error) {
+                Some(version) => self.handshaken_socket(version, host, port),
+                None => Err(error),
+            },
+            result => result,
+        }
+    }
+
+    #[cfg(feature = "async")]
+    fn handshaken_socket(&self, version: VersionDummy_Version, host: &str, port: u16) -> Result<TcpStream, Error> {
+        let mut stream = self.open_socket(host, port)?;
+        my_try!(stream.set_read_timeout(self.read_timeout));
+        Handshake {
+            stream: &mut stream,
+            version: version,
+            options: self,
+        }.run()?;
+        my_try!(stream.set_read_timeout(None));
+
+        Ok(stream)
+    }
+
+    /// Opens the TCP connection, trying each address `host` resolves to in turn.
+    fn open_socket(&self, host: &str, port: u16) -> Result<TcpStream, Error> {
+        let stream = match self.connect_timeout {
+            None => my_try!(TcpStream::connect((host, port))),
+            Some(timeout) => {
+                let mut result = Err(Error::ServerError(format!("Could not resolve {}:{}", host, port)));
+                for addr in my_try!((host, port).to_socket_addrs()) {
+                    result = TcpStream::connect_timeout(&addr, timeout).map_err(|error| Error::ServerError(format!("{}", error)));
+                    if result.is_ok() {
+                        break;
+                    }
+                }
+
+                result?
+            },
+        };
+        my_try!(stream.set_nodelay(self.nodelay));
+        my_try!(stream.set_write_timeout(self.write_timeout));
+        my_try!(TcpStreamExt::set_keepalive(&stream, self.keepalive));
+
+        Ok(stream)
+    }
+
+    #[cfg(not(feature = "tls"))]
+    fn open_transport(&self, host: &str, port: u16) -> Result<Transport, Error> {
+        Ok(Transport::Tcp(self.open_socket(host, port)?))
+    }
+
+    #[cfg(feature = "tls")]
+    fn open_transport(&self, host: &str, port: u16) -> Result<Transport, Error> {
+        let stream = self.open_socket(host, port)?;
+
+        match self.tls {
+            Some(ref tls) => Ok(Transport::Tls(tls.connect(host, stream)?)),
+            None => Ok(Transport::Tcp(stream)),
+        }
+    }
 }
 
-#[derive(Debug,RustcDecodable)]
-struct ServerSuccessResponse {
-    authentication: String,
-    success: bool,
+/// Represents a database connection.
+///
+/// A connection can be shared between threads (and cursors); queries are written under a lock,
+/// and a background thread reads the responses and hands each one to whoever is waiting on its
+/// token.  The server is free to answer queries in a different order than they were sent.
+pub struct Connection {
+    options: ConnectOptions,
+    // The handshake version the connection was opened with.
+    version: VersionDummy_Version,
+    stream: Arc<Mutex<Transport>>,
+    // A handle on the same socket as `stream`, used to wait for incoming data and to shut down
+    // without taking the lock.
+    socket: Mutex<TcpStream>,
+    dispatch: Dispatch<Sender<Result<QueryResponse, Error>>>,
+    // Held while a broken socket is being replaced, so only one thread reconnects.
+    reconnecting: Mutex<()>,
 }
 
-#[derive(RustcDecodable)]
-struct ServerErrorResponse {
-    error: String,
-    error_code: i64,
-    success: bool,
+/// Runs the handshake over a freshly opened stream, before anything else is sent on it.
+struct Handshake<'a, S: 'a> {
+    stream: &'a mut S,
+    version: VersionDummy_Version,
+    options: &'a ConnectOptions,
 }
 
-impl Connection {
-    fn send_version_number(&self) -> Result<(), Error> {
-        my_try!(self.stream.borrow_mut().write_u32::<LittleEndian>(VersionDummy_Version::V1_0 as u32));
-        my_try!(self.stream.borrow_mut().flush());
+impl<'a, S: Read + Write> Handshake<'a, S> {
+    fn send_version_number(&mut self) -> Result<(), Error> {
+        let version = self.version;
+
+        self.write_message(&protocol::version_number(version as u32))
+    }
+
+    fn write_message(&mut self, message: &[u8]) -> Result<(), Error> {
+        my_try!(self.stream.write_all(message));
+        my_try!(self.stream.flush());
 
         Ok(())
     }
 
-    fn read_stream_until_null(stream: &TcpStream) -> Result<String, Error> {
+    /// Reads n bytes off the TCP stream, until a NULL byte is found.  The NULL byte is then
+    /// discarded, and the rest of the data is returned as a string.
+    fn read_until_null(&mut self) -> Result<String, Error> {
         let mut recv = vec![];
 
-        match BufReader::new(stream).read_until(0, &mut recv) {
+        match BufReader::new(&mut *self.stream).read_until(0, &mut recv) {
             Ok(_) => {
                 let _ = recv.pop();
                 let resp = my_try!(String::from_utf8(recv));
@@ -75,201 +396,423 @@ impl Connection {
         }
     }
 
-    /// Reads n bytes off the TCP stream, until a NULL byte is found.  The NULL byte is then
-    /// discarded, and the rest of the data is returned as a string.
-    fn read_until_null(&self) -> Result<String, Error> {
-        let mut result = None;
+    fn parse_protocol_response(&mut self) -> Result<ProtocolSuccessResponse, Error> {
+        let resp = self.read_until_null()?;
 
-        Ref::map(self.stream.borrow(), |stream| {
-            result = Some(Connection::read_stream_until_null(stream));
+        protocol::parse_protocol_response(&resp)
+    }
 
-            stream
-        });
+    /// Sends the first client handshake response with authentication.
+    fn send_client_first_message(&mut self) -> Result<ServerFirst<'a>, Error> {
+        let options = self.options;
+        let (server_first, message) = protocol::client_first_message(&options.user, &options.password)?;
+        self.write_message(&message)?;
 
-        result.unwrap()
+        Ok(server_first)
     }
 
-    fn parse_protocol_response(&self) -> Result<ProtocolSuccessResponse, Error> {
-        let resp = my_try!(self.read_until_null());
+    fn parse_server_message(&mut self) -> Result<ServerSuccessResponse, Error> {
+        let resp = self.read_until_null()?;
 
-        match json::decode::<ProtocolSuccessResponse>(resp.as_str()) {
-            Ok(obj) => if obj.success {
-                Ok(obj)
-            } else {
-                // Should never happen, but better to have the check than not.
-                Err(Error::ServerError("Received a success response from RethinkDB with success = false.".to_owned()))
-            },
-            Err(_) => Err(Error::ServerError(resp)),
+        protocol::parse_server_message(&resp)
+    }
+
+    /// Sends the final client message in the authentication handshake.
+    fn send_client_final_message(&mut self, client_final: ClientFinal) -> Result<ServerFinal, Error> {
+        let (server_final, message) = protocol::client_final_message(client_final)?;
+        self.write_message(&message)?;
+
+        Ok(server_final)
+    }
+
+    fn run(&mut self) -> Result<(), Error> {
+        match self.version {
+            VersionDummy_Version::V1_0 => self.scram_handshake(),
+            _ => self.legacy_handshake(),
         }
     }
 
-    /// Sends the first client handshake response with authentication.  Should send something like:
-    ///
-    /// ```json
-    /// {
-    ///   "authentication": "n,,n=user,r=rOprNGfwEbeRWgbNEkqO",
-    ///   "authentication_method": "SCRAM-SHA-256",
-    ///   "protocol_version": 0
-    /// }
-    /// ```
-    fn send_client_first_message(&self) -> Result<ServerFirst, Error> {
-        let client_first = my_try!(ClientFirst::new(&self.user, &self.password, None));
-        let (server_first, auth) = client_first.client_first();
-        let mut message = BTreeMap::new();
-        message.insert("authentication".to_owned(), Json::String(auth));
-        let method = "SCRAM-SHA-256".to_owned();
-        message.insert("authentication_method".to_owned(), Json::String(method));
-        message.insert("protocol_version".to_owned(), Json::I64(SUB_PROTOCOL_VERSION));
-        let encoded = my_try!(json::encode(&message));
-        my_try!(self.stream.borrow_mut().write(&encoded.as_bytes()));
-        my_try!(self.stream.borrow_mut().write_u8(0));
-        my_try!(self.stream.borrow_mut().flush());
+    /// Uses the handshake for V1_0, defined in https://rethinkdb.com/docs/writing-drivers/.
+    fn scram_handshake(&mut self) -> Result<(), Error> {
+        self.send_version_number()?;
+        let _ = self.parse_protocol_response()?;
+        let server_first = self.send_client_first_message()?;
+        let client_first_response = self.parse_server_message()?;
+        let client_final = my_try!(server_first.handle_server_first(&client_first_response.authentication));
+        let server_final = self.send_client_final_message(client_final)?;
+        let client_final_response = self.parse_server_message()?;
+        my_try!(server_final.handle_server_final(&client_final_response.authentication));
 
-        Ok(server_first)
+        Ok(())
     }
 
-    /// Parses messages from the server, as defined for the RethinkDB handshake in
-    /// https://rethinkdb.com/docs/writing-drivers/
-    fn parse_server_message(&self) -> Result<ServerSuccessResponse, Error> {
-        let resp = my_try!(self.read_until_null());
+    /// Uses the handshake for V0_3 and V0_4: everything is sent at once, and the server answers with
+    /// a single NULL-terminated string.
+    fn legacy_handshake(&mut self) -> Result<(), Error> {
+        let message = protocol::legacy_handshake_message(self.version, &self.options.auth_key)?;
+        self.write_message(&message)?;
+        let resp = self.read_until_null()?;
 
-        match json::decode::<ServerSuccessResponse>(resp.as_str()) {
-            Ok(success_obj) => if success_obj.success {
-                Ok(success_obj)
-            } else {
-                // Should never happen, but better to have the check than not.
-                Err(Error::ServerError("Received a success response from RethinkDB with success = false.".to_owned()))
-            },
-            Err(_) => match json::decode::<ServerErrorResponse>(resp.as_str()) {
-                Ok(error_obj) => if !error_obj.success {
-                    // An error code within [10, 20] is defined to return a ReqlAuthError.
-                    if error_obj.error_code >= 10 && error_obj.error_code <= 20 {
-                        Err(Error::ReqlAuthError)
-                    } else {
-                        Err(Error::ServerError(error_obj.error))
-                    }
-                } else {
-                    // Should never happen, but better to have the check than not.
-                    Err(Error::ServerError("Received an error response from RethinkDB with success = true.".to_owned()))
-                },
-                // We don't have either a success or an error response.  Very weird.
-                Err(error) => Err(Error::ServerError(format!("{}", error))),
-            }
-        }
+        protocol::parse_legacy_response(&resp)
     }
+}
 
-    /// Sends the final client message in the authentication handshake.  Should look like:
-    ///
-    /// ```json
-    /// {
-    ///   "authentication": "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
-    /// }
-    /// ```
-    fn send_client_final_message(&self, client_final: ClientFinal) -> Result<ServerFinal, Error> {
-        let (server_final, auth) = client_final.client_final();
-        let mut message = BTreeMap::new();
-        message.insert("authentication".to_owned(), auth);
-        let encoded = my_try!(json::encode(&message));
-
-        my_try!(self.stream.borrow_mut().write(&encoded.as_bytes()));
-        my_try!(self.stream.borrow_mut().write_u8(0));
-        my_try!(self.stream.borrow_mut().flush());
+impl Connection {
+    fn write_message(&self, message: &[u8]) -> Result<(), Error> {
+        let mut stream = my_try!(self.stream.lock());
+        my_try!(stream.write_all(message));
+        my_try!(stream.flush());
 
-        Ok(server_final)
+        Ok(())
     }
 
-    /// Uses the handshake for V1_0, defined in https://rethinkdb.com/docs/writing-drivers/.
     fn handshake(&self) -> Result<(), Error> {
-        my_try!(self.send_version_number());
-        let _ = my_try!(self.parse_protocol_response());
-        let server_first = my_try!(self.send_client_first_message());
-        let client_first_response = my_try!(self.parse_server_message());
-        let client_final = my_try!(server_first.handle_server_first(&client_first_response.authentication));
-        let server_final = my_try!(self.send_client_final_message(client_final));
-        let client_final_response = my_try!(self.parse_server_message());
-        my_try!(server_final.handle_server_final(&client_final_response.authentication));
+        let mut stream = my_try!(self.stream.lock());
 
-        Ok(())
+        Handshake {
+            stream: &mut *stream,
+            version: self.version,
+            options: &self.options,
+        }.run()
     }
 
     /// Connects to the provided server `host` and `port`.
     pub fn connect(host: &str, port: u16, user: &str, password: &str) -> Result<Connection, Error> {
-        let stream = my_try!(TcpStream::connect((host, port)));
+        Connection::connect_with(ConnectOptions::new(host, port).user(user).password(password))
+    }
+
+    /// Connects with everything `options` sets up: socket options, TLS, and the defaults sent
+    /// along with every query.  The hosts are tried in order, and the first one to complete the
+    /// handshake is used.
+    pub fn connect_with(options: ConnectOptions) -> Result<Connection, Error> {
+        let mut last_error = Error::ServerError("There are no hosts to connect to.".to_owned());
+        for (host, port) in options.hosts() {
+            match Connection::negotiate(options.clone(), &host, port) {
+                Ok(conn) => return Ok(conn),
+                Err(error) => last_error = error,
+            }
+        }
+
+        Err(last_error)
+    }
+
+    fn negotiate(options: ConnectOptions, host: &str, port: u16) -> Result<Connection, Error> {
+        let handshake = options.handshake;
+        match Connection::open(options.clone(), handshake.first_attempt(), host, port) {
+            // Servers that turn down a handshake version close the connection, so the next one has
+            // to be tried on a new one.
+            Err(error) => match handshake.fallback(&error) {
+                Some(version) => Connection::open(options, version, host, port),
+                None => Err(error),
+            },
+            result => result,
+        }
+    }
+
+    fn open(options: ConnectOptions, version: VersionDummy_Version, host: &str, port: u16) -> Result<Connection, Error> {
+        let transport = options.open_transport(host, port)?;
+        let socket = my_try!(transport.socket().try_clone());
+        // The read timeout covers the handshake; `spawn_reader` replaces it afterwards.
+        my_try!(socket.set_read_timeout(options.read_timeout));
         let conn = Connection{
-            host: host.to_owned(),
-            port: port,
-            stream: RefCell::new(stream),
-            query_token: Cell::new(0),
-            user: user.to_owned(),
-            password: password.to_owned(),
+            options: options,
+            version: version,
+            stream: Arc::new(Mutex::new(transport)),
+            socket: Mutex::new(socket),
+            dispatch: Dispatch::new(),
+            reconnecting: Mutex::new(()),
         };
 
-        match conn.handshake() {
+        match conn.handshake().and_then(|_| conn.spawn_reader()) {
             Ok(()) => Ok(conn),
             Err(error) => {
-                Ref::map(conn.stream.borrow(), |stream| {
-                    stream.shutdown(Shutdown::Both).unwrap();
-
-                    stream
-                });
+                conn.shutdown();
 
                 Err(error)
             },
         }
     }
 
-    fn send_query(&self, tree: &Tree) -> Result<(), Error> {
-        let token = self.query_token.get();
-        // Increment the token for the next request.
-        self.query_token.set(token.wrapping_add(1));
-        let tree = my_try!(json::encode(&tree.to_json()));
-        let len = tree.as_bytes().len();
-        if len > (u32::MAX as usize) {
-            return Err(Error::QueryTooLarge(len));
+    pub fn options(&self) -> &ConnectOptions {
+        &self.options
+    }
+
+    /// The handshake version the connection was opened with, which tells what the server supports;
+    /// e.g. SERVER_INFO queries are only understood by servers speaking V1_0.
+    pub fn version(&self) -> VersionDummy_Version {
+        self.version
+    }
+
+    /// Closes the socket, which also stops the reader thread.
+    fn shutdown(&self) {
+        if let Ok(socket) = self.socket.lock() {
+            let _ = socket.shutdown(Shutdown::Both);
         }
+    }
 
-        my_try!(self.stream.borrow_mut().write_u64::<LittleEndian>(token));
-        my_try!(self.stream.borrow_mut().write_u32::<LittleEndian>(len as u32));
-        my_try!(self.stream.borrow_mut().write(&tree.as_bytes()));
+    /// Starts the thread that reads every response off the socket once the handshake is done.
+    fn spawn_reader(&self) -> Result<(), Error> {
+        let stream = self.stream.clone();
+        let socket = my_try!(my_try!(self.socket.lock()).try_clone());
+        my_try!(socket.set_read_timeout(my_try!(self.stream.lock()).reader_timeout()));
+        let dispatch = self.dispatch.clone();
+        my_try!(thread::Builder::new()
+            .name("rethinkdb-reader".to_owned())
+            .spawn(move || Connection::route_responses(stream, socket, dispatch)));
 
         Ok(())
     }
 
-    // TODO: Worry about how to handle unordered responses.  We should probably loop over the
-    // stream once the handshake is done, trying to read each response and putting it in a hashed
-    // collection.
-    fn parse_response_from_stream(stream: &TcpStream) -> Result<QueryResponse, Error> {
-        let mut reader = BufReader::new(stream);
-        let token = my_try!(reader.read_u64::<LittleEndian>());
-        let len = my_try!(reader.read_u32::<LittleEndian>());
-        let mut recv = vec![];
-        let _ = my_try!(reader.take(len as u64).read(&mut recv));
-        let response = my_try!(Json::from_str(my_try!(str::from_utf8(&recv))));
+    /// Makes sure there's a working socket to send a new query on, reconnecting if the options
+    /// allow it.
+    fn ensure_open(&self) -> Result<(), Error> {
+        let (reason, backoff) = match self.dispatch.reconnect_with(&self.options.reconnect)? {
+            Some(reconnect) => reconnect,
+            None => return Ok(()),
+        };
+
+        let _reconnecting = my_try!(self.reconnecting.lock());
+        // Another thread may have reconnected while we were waiting for the lock.
+        if !self.is_closed() {
+            return Ok(());
+        }
+
+        self.options.retry_hosts(&backoff, reason, |host, port| self.relink(host, port))
+    }
+
+    /// Replaces the broken socket with a new one to `host`, and starts reading from it.  Must only
+    /// be called once the reader thread of the old socket has stopped.
+    fn relink(&self, host: &str, port: u16) -> Result<(), Error> {
+        let transport = self.options.open_transport(host, port)?;
+        let socket = my_try!(transport.socket().try_clone());
+        my_try!(socket.set_read_timeout(self.options.read_timeout));
+        *my_try!(self.stream.lock()) = transport;
+        *my_try!(self.socket.lock()) = socket;
+        if let Err(error) = self.handshake() {
+            self.shutdown();
+
+            return Err(error);
+        }
+
+        if let Err(error) = self.dispatch.reopen(|| self.spawn_reader()) {
+            self.shutdown();
+
+            return Err(error);
+        }
+
+        Ok(())
+    }
 
-        Ok(QueryResponse {
-            query_token: token,
-            length: len,
-            response: response,
-        })
+    /// Whether the socket has failed or been closed.  A closed connection fails every query, until
+    /// it reconnects.
+    pub fn is_closed(&self) -> bool {
+        self.dispatch.is_closed()
     }
 
-    fn parse_response(&self) -> Result<QueryResponse, Error> {
-        let mut result = None;
+    /// Writes `query` under `token`.  Failing to write means the connection is lost; anything else
+    /// (e.g. a query too large to send) leaves it usable.
+    pub(crate) fn send_query(&self, token: u64, query: &Query) -> Result<(), Error> {
+        let frame = protocol::encode_query(token, query, protocol::MAX_QUERY_LEN)?;
+
+        // The whole frame is written at once, so frames from different threads never interleave.
+        if let Err(error) = self.write_message(&frame) {
+            // Part of the frame may have made it out, so the server can't make sense of anything
+            // we'd send after it.
+            self.shutdown();
+
+            return Err(Error::ConnectionLost(format!("{}", error)));
+        }
+
+        Ok(())
+    }
 
-        Ref::map(self.stream.borrow(), |stream| {
-            result = Some(Connection::parse_response_from_stream(stream));
+    /// Reads whatever the server has sent so far onto the end of `buffer`, and returns the number
+    /// of bytes read.
+    ///
+    /// Writers share the transport, so the lock is only taken once the socket has data.  A TLS
+    /// stream can still block with the lock held, e.g. when the data turns out to be a session
+    /// ticket rather than a response, which is why TLS sockets get a read timeout.
+    fn read_responses(stream: &Mutex<Transport>, socket: &TcpStream, buffer: &mut Vec<u8>) -> Result<usize, Error> {
+        let mut peeked = [0; 1];
+        match socket.peek(&mut peeked) {
+            Ok(0) => return Err(Error::ServerError("The server closed the connection.".to_owned())),
+            Ok(_) => {},
+            Err(ref error) if Connection::is_timeout(error.kind()) => return Ok(0),
+            Err(error) => return Err(Error::ServerError(format!("{}", error))),
+        }
 
-            stream
-        });
+        // Large enough for a whole TLS record, so a read doesn't leave half of one decrypted
+        // inside the TLS stream.
+        let mut chunk = [0; 16 * 1024];
+        let mut total = 0;
+        let mut stream = my_try!(stream.lock());
+        loop {
+            match stream.read(&mut chunk) {
+                Ok(0) => return Err(Error::ServerError("The server closed the connection.".to_owned())),
+                Ok(n) => {
+                    buffer.extend_from_slice(&chunk[..n]);
+                    total += n;
+                },
+                Err(ref error) if Connection::is_timeout(error.kind()) => return Ok(total),
+                Err(error) => return Err(Error::ServerError(format!("{}", error))),
+            }
 
-        result.unwrap()
+            if stream.buffered() == 0 {
+                return Ok(total);
+            }
+        }
     }
 
-    pub fn db_create(&self, name: &str) -> Result<QueryResponse, Error> {
-        my_try!(self.send_query(&Tree::Query {
-            head: Term_TermType::DB_CREATE,
-            tail: vec![Tree::Datum(Json::String(name.to_owned()))],
-        }));
-        Ok(my_try!(self.parse_response()))
+    fn is_timeout(kind: ErrorKind) -> bool {
+        kind == ErrorKind::WouldBlock || kind == ErrorKind::TimedOut || kind == ErrorKind::Interrupted
+    }
+
+    /// Runs on the reader thread, handing each frame to `dispatch` until the socket fails.
+    fn route_responses(stream: Arc<Mutex<Transport>>, socket: TcpStream, dispatch: Dispatch<Sender<Result<QueryResponse, Error>>>) {
+        let mut buffer = vec![];
+
+        let reason = loop {
+            if let Err(error) = Connection::read_responses(&stream, &socket, &mut buffer) {
+                break format!("{}", error);
+            }
+
+            while let Some((token, recv)) = protocol::take_frame(&mut buffer) {
+                if dispatch.route(token, &recv).is_err() {
+                    return;
+                }
+            }
+        };
+
+        dispatch.close(reason);
+    }
+
+    /// Sends `query` under `token` and waits for the server's answer, up to the read timeout.
+    fn send_and_receive(&self, token: u64, query: &Query) -> Result<QueryResponse, Error> {
+        self.send_and_wait(token, query, self.options.read_timeout)
+    }
+
+    /// Sends `query` under `token` and waits up to `timeout` for the server's answer.
+    fn send_and_wait(&self, token: u64, query: &Query, timeout: Option<Duration>) -> Result<QueryResponse, Error> {
+        let (sender, receiver) = mpsc::channel();
+        self.dispatch.send(token, sender, || self.send_query(token, query))?;
+
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => return match receiver.recv() {
+                Ok(response) => response,
+                Err(error) => Err(Error::ServerError(format!("{}", error))),
+            },
+        };
+
+        match receiver.recv_timeout(timeout) {
+            Ok(response) => response,
+            Err(RecvTimeoutError::Timeout) => {
+                self.dispatch.forget(token);
+
+                Err(Error::ResponseTimeout(timeout))
+            },
+            Err(error) => Err(Error::ServerError(format!("{}", error))),
+        }
+    }
+
+    /// Sends a query that starts something new on the server, under a new token.  This is where a
+    /// broken connection gets replaced.
+    fn send_new_query(&self, query: &Query) -> Result<QueryResponse, Error> {
+        self.ensure_open()?;
+        let token = self.dispatch.next_token()?;
+
+        self.send_and_receive(token, query)
+    }
+
+    /// Sends `query` as a new START query and returns the first response as is.  Its token is the
+    /// one to pass to `continue_query` and `stop_query`.
+    pub fn start_query<T: IntoTree>(&self, query: T) -> Result<QueryResponse, Error> {
+        let tree = query.into_tree()?;
+        let replay = self.options.replay_reads && tree.is_read_only();
+
+        let mut query = Query::start(tree);
+        query.global_optargs = self.options.query_optargs();
+
+        match self.send_new_query(&query) {
+            Err(ref error) if replay && error.is_retryable() => self.send_new_query(&query),
+            result => result,
+        }
+    }
+
+    /// Runs `query` and decodes its result: the value for queries returning one, or every element
+    /// for sequences, fetching the remaining batches first.  Changefeeds never end, so they have
+    /// to go through `run_cursor`.
+    pub fn run<T: DeserializeOwned, Q: IntoTree>(&self, query: Q) -> Result<T, Error> {
+        let response = self.start_query(query)?;
+        if !response.response.is_partial() {
+            return self.options.decode(response.response.into_value()?);
+        }
+
+        let results = Cursor::<Value>::new(self, response)?.collect::<Result<Vec<Value>, Error>>()?;
+        self.options.decode(Value::Array(results))
+    }
+
+    /// Runs a write and decodes its result, failing if any document couldn't be written.  Decode
+    /// into `WriteResult` with `run` to handle partial failures by hand.
+    pub fn write<T: DeserializeOwned, Q: IntoTree>(&self, query: Q) -> Result<WriteResult<T>, Error> {
+        WriteResult::checked(self.run::<Value, Q>(query)?)
+    }
+
+    /// Runs `query` and returns a cursor over its results, which fetches the remaining batches from
+    /// the server as it is iterated.
+    pub fn run_cursor<T: DeserializeOwned, Q: IntoTree>(&self, query: Q) -> Result<Cursor<T>, Error> {
+        let response = self.start_query(query)?;
+
+        Cursor::new(self, response)
+    }
+
+    /// Runs a changefeed as a `Feed`, which sends `query` again whenever the connection has to be
+    /// re-established.
+    pub fn run_feed<T: DeserializeOwned, Q: IntoTree>(&self, query: Q) -> Result<Feed<T>, Error> {
+        Feed::new(self, query.into_tree()?)
+    }
+
+    /// Fetches the next batch of the query started under `token`.
+    pub fn continue_query(&self, token: u64) -> Result<QueryResponse, Error> {
+        self.send_and_receive(token, &Query::continue_query())
+    }
+
+    /// Like `continue_query`, for changefeeds: the server only answers once there are changes, so
+    /// the read timeout doesn't apply.
+    pub fn continue_feed(&self, token: u64) -> Result<QueryResponse, Error> {
+        self.send_and_wait(token, &Query::continue_query(), None)
+    }
+
+    /// Stops the query started under `token`, so the server stops producing batches for it.
+    pub fn stop_query(&self, token: u64) -> Result<QueryResponse, Error> {
+        self.send_and_receive(token, &Query::stop())
+    }
+
+    /// Waits until the server has processed every noreply query sent on this connection.
+    pub fn noreply_wait(&self) -> Result<QueryResponse, Error> {
+        self.send_new_query(&Query::noreply_wait())
+    }
+
+    /// Like `noreply_wait`, giving up after `timeout`.  A closed connection fails right away
+    /// instead of reconnecting.
+    pub(crate) fn noreply_wait_within(&self, timeout: Duration) -> Result<QueryResponse, Error> {
+        self.send_and_wait(self.dispatch.next_token()?, &Query::noreply_wait(), Some(timeout))
+    }
+
+    /// Asks the server for its id, name and proxy status.
+    pub fn server_info(&self) -> Result<QueryResponse, Error> {
+        self.send_new_query(&Query::server_info())
+    }
+
+    /// Like `server_info`, giving up after `timeout`.  A closed connection fails right away instead
+    /// of reconnecting.
+    pub(crate) fn server_info_within(&self, timeout: Duration) -> Result<QueryResponse, Error> {
+        self.send_and_wait(self.dispatch.next_token()?, &Query::server_info(), Some(timeout))
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.shutdown();
     }
 }
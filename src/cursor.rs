@@ -0,0 +1,101 @@
+use connection::Connection;
+use error::Error;
+use protocol::QueryResponse;
+use reql::query::Query;
+use reql::response::FeedType;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+
+/// Streams the results of a query.  Results come back from the server in batches; as long as the
+/// last batch was a SUCCESS_PARTIAL, the next one is fetched with a CONTINUE query (on the same
+/// token) once the buffered results run out.  Each result is decoded into a `T` as it is yielded.
+pub struct Cursor<'a, T = Value> {
+    conn: &'a Connection,
+    token: u64,
+    buffer: VecDeque<Value>,
+    feed: Option<FeedType>,
+    // Whether the server has sent its last batch for this token.
+    done: bool,
+    results: PhantomData<T>,
+}
+
+impl<'a, T: DeserializeOwned> Cursor<'a, T> {
+    /// Builds a cursor from the first response to a START query.
+    pub fn new(conn: &'a Connection, response: QueryResponse) -> Result<Cursor<'a, T>, Error> {
+        let mut cursor = Cursor {
+            conn: conn,
+            token: response.query_token,
+            buffer: VecDeque::new(),
+            feed: response.response.feed_type(),
+            done: false,
+            results: PhantomData,
+        };
+        cursor.load(response)?;
+
+        Ok(cursor)
+    }
+
+    pub fn token(&self) -> u64 {
+        self.token
+    }
+
+    /// The kind of changefeed the cursor follows, or `None` for plain results.  Changefeeds only
+    /// end when the cursor is dropped, or with an error.
+    pub fn feed_type(&self) -> Option<FeedType> {
+        self.feed
+    }
+
+    /// Buffers the results of a batch.
+    fn load(&mut self, response: QueryResponse) -> Result<(), Error> {
+        self.done = !response.response.is_partial();
+        let batch = response.response.into_batch()?;
+        self.buffer.extend(batch);
+
+        Ok(())
+    }
+}
+
+impl<'a, T: DeserializeOwned> Iterator for Cursor<'a, T> {
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Result<T, Error>> {
+        // Batches can legitimately be empty, so keep asking until we get a result or the server
+        // says it is finished.
+        loop {
+            if let Some(result) = self.buffer.pop_front() {
+                return Some(self.conn.options().decode(result));
+            }
+            if self.done {
+                return None;
+            }
+
+            let next = match self.feed {
+                Some(_) => self.conn.continue_feed(self.token),
+                None => self.conn.continue_query(self.token),
+            };
+            let loaded = match next {
+                Ok(response) => self.load(response),
+                Err(error) => Err(error),
+            };
+            if let Err(error) = loaded {
+                // Either the server ended the query with an error, or the connection is no longer
+                // usable.  There's nothing more to read in both cases.
+                self.done = true;
+
+                return Some(Err(error));
+            }
+        }
+    }
+}
+
+impl<'a, T> Drop for Cursor<'a, T> {
+    /// Dropping a cursor before it is exhausted tells the server to stop the query, so it doesn't
+    /// keep batches around for us.  Nobody waits for the answer; the reader thread drops it.
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.conn.send_query(self.token, &Query::stop());
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use backoff::Backoff;
+use error::Error;
+#[cfg(feature = "async")]
+use futures::sync::oneshot;
+use protocol::{self, QueryResponse};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc;
+
+/// How a caller waiting on a query is handed the outcome: over a channel for `Connection`, and a
+/// oneshot for `AsyncConnection`.
+pub trait Reply {
+    fn reply(self, response: Result<QueryResponse, Error>);
+}
+
+impl Reply for mpsc::Sender<Result<QueryResponse, Error>> {
+    fn reply(self, response: Result<QueryResponse, Error>) {
+        let _ = self.send(response);
+    }
+}
+
+#[cfg(feature = "async")]
+impl Reply for oneshot::Sender<Result<QueryResponse, Error>> {
+    fn reply(self, response: Result<QueryResponse, Error>) {
+        let _ = self.send(response);
+    }
+}
+
+/// The bookkeeping a connection shares between the callers sending queries and whatever reads the
+/// responses: the query tokens, who is waiting on which one, and whether the socket is still up.
+/// Clones share the same state.
+pub struct Dispatch<S> {
+    state: Arc<Mutex<State<S>>>,
+}
+
+struct State<S> {
+    next_token: u64,
+    // The callers still owed a response, keyed by query token.
+    senders: HashMap<u64, S>,
+    // Set once the reader has stopped, with the reason it stopped.  No more responses will arrive
+    // after that.
+    closed: Option<String>,
+    // The first token handed out since the last reconnect.  The server has never heard of tokens
+    // from before that.
+    first_token: u64,
+}
+
+impl<S> Clone for Dispatch<S> {
+    fn clone(&self) -> Dispatch<S> {
+        Dispatch {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<S: Reply> Dispatch<S> {
+    pub fn new() -> Dispatch<S> {
+        Dispatch {
+            state: Arc::new(Mutex::new(State {
+                next_token: 0,
+                senders: HashMap::new(),
+                closed: None,
+                first_token: 0,
+            })),
+        }
+    }
+
+    /// Hands out the token for a new query.  Every follow-up (CONTINUE, STOP) for that query must
+    /// reuse the same token.
+    pub fn next_token(&self) -> Result<u64, Error> {
+        let mut state = my_try!(self.state.lock());
+        let token = state.next_token;
+        // Increment the token for the next request.
+        state.next_token = token.wrapping_add(1);
+
+        Ok(token)
+    }
+
+    /// Registers `sender` for the answer to `token`, then calls `send` to write the query.
+    /// Registering first means the reader can't see the response before anyone is waiting on it.
+    pub fn send<F: FnOnce() -> Result<(), Error>>(&self, token: u64, sender: S, send: F) -> Result<(), Error> {
+        {
+            let mut state = my_try!(self.state.lock());
+            if let Some(ref reason) = state.closed {
+                return Err(Error::ConnectionLost(reason.clone()));
+            }
+            if token < state.first_token {
+                return Err(Error::ConnectionLost("The query was started before the connection was re-established.".to_owned()));
+            }
+            state.senders.insert(token, sender);
+        }
+
+        if let Err(error) = send() {
+            self.forget(token);
+
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    /// Stops waiting on `token`, e.g. once the caller has given up on it.  The answer is dropped if
+    /// it still comes.
+    pub fn forget(&self, token: u64) {
+        if let Ok(mut state) = self.state.lock() {
+            state.senders.remove(&token);
+        }
+    }
+
+    /// Hands a frame read off the socket to whoever is waiting on its token.  Frames nobody is
+    /// waiting for (e.g. the answer to a STOP sent by a dropped cursor) are dropped.
+    pub fn route(&self, token: u64, recv: &[u8]) -> Result<(), Error> {
+        let response = protocol::decode_response(token, recv);
+        let sender = my_try!(self.state.lock()).senders.remove(&token);
+        if let Some(sender) = sender {
+            sender.reply(response);
+        }
+
+        Ok(())
+    }
+
+    /// Called once the reader has stopped: everyone still waiting gets the error, and so does every
+    /// query sent afterwards, until the connection is reopened.
+    pub fn close(&self, reason: String) {
+        if let Ok(mut state) = self.state.lock() {
+            for (_, sender) in state.senders.drain() {
+                sender.reply(Err(Error::ConnectionLost(reason.clone())));
+            }
+            state.closed = Some(reason);
+        }
+    }
+
+    /// Whether the socket has failed or been closed.
+    pub fn is_closed(&self) -> bool {
+        match self.state.lock() {
+            Ok(state) => state.closed.is_some(),
+            Err(_) => true,
+        }
+    }
+
+    /// `None` while the connection is open.  Otherwise, the reason it was lost and the backoff to
+    /// reconnect with, or `ConnectionLost` if `reconnect` is off.
+    pub fn reconnect_with(&self, reconnect: &Option<Backoff>) -> Result<Option<(String, Backoff)>, Error> {
+        let reason = match my_try!(self.state.lock()).closed {
+            Some(ref reason) => reason.clone(),
+            None => return Ok(None),
+        };
+
+        match *reconnect {
+            Some(ref backoff) => Ok(Some((reason, backoff.clone()))),
+            None => Err(Error::ConnectionLost(reason)),
+        }
+    }
+
+    /// Calls `relink` to put a new socket in place, and marks the connection open again if it
+    /// succeeds.  Does nothing if the connection is already open, e.g. because another caller got
+    /// there first.  Tokens handed out before this are turned away from then on.
+    pub fn reopen<F: FnOnce() -> Result<(), Error>>(&self, relink: F) -> Result<(), Error> {
+        let mut state = my_try!(self.state.lock());
+        if state.closed.is_none() {
+            return Ok(());
+        }
+
+        relink()?;
+        state.closed = None;
+        state.first_token = state.next_token;
+
+        Ok(())
+    }
+}
@@ -1,18 +1,55 @@
+use protocol::MAX_QUERY_LEN;
+use ql2::Response_ErrorType;
+use reql::results::WriteResult;
 use std::fmt::{self, Display, Formatter};
-use std::u32;
+use std::time::Duration;
 
 pub enum Error {
+    ConnectionLost(String),
+    DecodeError(String),
+    PoolTimeout(Duration),
     QueryTooLarge(usize),
     ReqlAuthError,
+    ReqlClientError(String),
+    ReqlCompileError(String),
+    ReqlDriverCompileError(String),
+    ReqlRuntimeError(Option<Response_ErrorType>, String),
+    ResponseTimeout(Duration),
     ServerError(String),
+    UnsupportedVersion(String),
+    /// What a write that failed on some documents returned: how many it failed on and why the first
+    /// one did, along with what did get written.
+    WriteError(Box<WriteResult>),
+}
+
+impl Error {
+    /// Whether the query might succeed if it's sent again.  The connection broke before the server
+    /// answered, so the query may or may not have run.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            &Error::ConnectionLost(_) => true,
+            _ => false,
+        }
+    }
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
-            &Error::QueryTooLarge(n) => write!(f, "Query was too large: max size is {} bytes but the query takes up {} bytes.", u32::MAX, n),
+            &Error::ConnectionLost(ref error) => write!(f, "The connection was lost: {}", error),
+            &Error::DecodeError(ref error) => write!(f, "Could not decode the result: {}", error),
+            &Error::PoolTimeout(timeout) => write!(f, "Timed out after {:?} waiting for a connection from the pool.", timeout),
+            &Error::QueryTooLarge(n) => write!(f, "Query was too large: max size is {} bytes but the query takes up {} bytes.", MAX_QUERY_LEN, n),
             &Error::ReqlAuthError => write!(f, "Authentication failed."),
+            &Error::ReqlClientError(ref error) => write!(f, "Client error: {}", error),
+            &Error::ReqlCompileError(ref error) => write!(f, "Compile error: {}", error),
+            &Error::ReqlDriverCompileError(ref error) => write!(f, "Invalid query: {}", error),
+            &Error::ReqlRuntimeError(Some(error_type), ref error) => write!(f, "Runtime error ({:?}): {}", error_type, error),
+            &Error::ReqlRuntimeError(None, ref error) => write!(f, "Runtime error: {}", error),
+            &Error::ResponseTimeout(timeout) => write!(f, "Timed out after {:?} waiting for a response.", timeout),
             &Error::ServerError(ref error) => write!(f, "{}", error),
+            &Error::UnsupportedVersion(ref error) => write!(f, "The server does not speak this version of the protocol: {}", error),
+            &Error::WriteError(ref result) => write!(f, "{} documents could not be written, the first because: {}", result.errors, result.first_error.as_ref().map_or("", String::as_str)),
         }
     }
 }
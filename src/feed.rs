@@ -0,0 +1,75 @@
+use connection::Connection;
+use cursor::Cursor;
+use error::Error;
+use reql::response::FeedType;
+use reql::results::Change;
+use reql::tree::Tree;
+use serde::de::DeserializeOwned;
+
+/// What a `Feed` yields.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FeedEvent<T> {
+    Change(Change<T>),
+    /// The connection was lost, and the changefeed started over once it was back.  Changes made in
+    /// between are missing; with `include_initial`, the current results follow again, so consumers
+    /// can rebuild their state from them.
+    Resync,
+}
+
+/// A changefeed that survives the connection breaking.  When its cursor fails with
+/// `Error::ConnectionLost`, it sends the original CHANGES query again, which reconnects the way
+/// `ConnectOptions::reconnect` says, and yields `FeedEvent::Resync` before carrying on.
+///
+/// A socket that dies without being closed is only noticed with `ConnectOptions::keepalive`.
+pub struct Feed<'a, T> {
+    conn: &'a Connection,
+    query: Tree,
+    // `None` once the feed has failed for good.
+    cursor: Option<Cursor<'a, Change<T>>>,
+}
+
+impl<'a, T: DeserializeOwned> Feed<'a, T> {
+    pub fn new(conn: &'a Connection, query: Tree) -> Result<Feed<'a, T>, Error> {
+        let cursor = conn.run_cursor(query.clone())?;
+
+        Ok(Feed {
+            conn: conn,
+            query: query,
+            cursor: Some(cursor),
+        })
+    }
+
+    /// The kind of changefeed the current cursor follows.
+    pub fn feed_type(&self) -> Option<FeedType> {
+        self.cursor.as_ref().and_then(Cursor::feed_type)
+    }
+}
+
+impl<'a, T: DeserializeOwned> Iterator for Feed<'a, T> {
+    type Item = Result<FeedEvent<T>, Error>;
+
+    fn next(&mut self) -> Option<Result<FeedEvent<T>, Error>> {
+        let next = match self.cursor {
+            Some(ref mut cursor) => cursor.next(),
+            None => return None,
+        };
+
+        match next {
+            Some(Ok(change)) => Some(Ok(FeedEvent::Change(change))),
+            Some(Err(ref error)) if error.is_retryable() => {
+                // The old cursor is done with, so dropping it doesn't send anything.
+                self.cursor = None;
+                match self.conn.run_cursor(self.query.clone()) {
+                    Ok(cursor) => {
+                        self.cursor = Some(cursor);
+
+                        Some(Ok(FeedEvent::Resync))
+                    },
+                    // Reconnecting is off, or gave up.
+                    Err(error) => Some(Err(error)),
+                }
+            },
+            other => other.map(|result| result.map(FeedEvent::Change)),
+        }
+    }
+}
@@ -1,14 +1,63 @@
 #[warn(unused_imports)]
+extern crate base64;
 extern crate byteorder;
+#[cfg(feature = "async")]
+extern crate futures;
+#[cfg(feature = "tls")]
+extern crate native_tls;
+extern crate net2;
+#[cfg(test)]
+#[macro_use]
+extern crate proptest;
 extern crate protobuf;
-extern crate rustc_serialize;
 extern crate scram;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+#[cfg(feature = "async")]
+extern crate tokio;
 
 #[macro_use]
 mod macros;
 
+mod admin;
+#[cfg(feature = "async")]
+mod async_connection;
+#[cfg(feature = "async")]
+mod async_cursor;
+mod backoff;
 mod connection;
+mod cursor;
+mod dispatch;
 mod error;
+mod feed;
+mod pool;
+mod protocol;
 mod reql;
 mod ql2;
 mod test;
+#[cfg(feature = "tls")]
+mod tls;
+mod transport;
+
+pub use admin::TableOptions;
+#[cfg(feature = "async")]
+pub use async_connection::{AsyncConnection, ReqlFuture};
+#[cfg(feature = "async")]
+pub use async_cursor::AsyncCursor;
+pub use backoff::Backoff;
+pub use connection::{ConnectOptions, Connection};
+pub use cursor::Cursor;
+pub use error::Error;
+pub use feed::{Feed, FeedEvent};
+pub use pool::{Pool, PoolOptions, PooledConnection};
+pub use protocol::HandshakeVersion;
+pub use reql::r::{r, R};
+pub use reql::response::FeedType;
+pub use reql::results::{Change, ChangeType, DbConfig, DbCreated, DbDropped, FeedState, IndexCreated, IndexDropped, IndexRenamed, IndexStatus, Joined, Rebalanced, Reconfigured, ReplicaStatus, ShardConfig, ShardStatus, Synced, TableConfig, TableCreated, TableDropped, TableReadiness, TableStatus, Waited, WriteResult};
+pub use reql::term::{IntoTerm, Term};
+pub use reql::tree::{IntoTree, Tree};
+pub use reql::types::{Binary, Field, Format, Geometry, Group, Grouped, Literal, Point, Time};
+#[cfg(feature = "tls")]
+pub use tls::TlsOptions;
@@ -0,0 +1,257 @@
+use connection::{ConnectOptions, Connection};
+use error::Error;
+use ql2::{Response_ResponseType, VersionDummy_Version};
+use std::cmp;
+use std::collections::VecDeque;
+use std::ops::Deref;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// How a `Pool` sizes itself and how long it lets connections sit around.
+pub struct PoolOptions {
+    min_size: usize,
+    max_size: usize,
+    checkout_timeout: Duration,
+    idle_timeout: Option<Duration>,
+}
+
+impl PoolOptions {
+    pub fn new() -> PoolOptions {
+        PoolOptions {
+            min_size: 1,
+            max_size: 10,
+            checkout_timeout: Duration::from_secs(30),
+            idle_timeout: Some(Duration::from_secs(600)),
+        }
+    }
+
+    /// The number of connections opened up front, and kept open even when they are idle.
+    pub fn min_size(mut self, min_size: usize) -> PoolOptions {
+        self.min_size = min_size;
+        self
+    }
+
+    /// The most connections the pool will have open at once, counting the ones checked out.
+    pub fn max_size(mut self, max_size: usize) -> PoolOptions {
+        self.max_size = max_size;
+        self
+    }
+
+    /// How long `Pool::get` waits for a connection to be returned when the pool is at its max size.
+    pub fn checkout_timeout(mut self, checkout_timeout: Duration) -> PoolOptions {
+        self.checkout_timeout = checkout_timeout;
+        self
+    }
+
+    /// Connections idle for longer than this are closed, as long as that leaves at least `min_size`
+    /// open.  `None` keeps them forever.
+    pub fn idle_timeout(mut self, idle_timeout: Option<Duration>) -> PoolOptions {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+}
+
+impl Default for PoolOptions {
+    fn default() -> PoolOptions {
+        PoolOptions::new()
+    }
+}
+
+struct IdleConnection {
+    conn: Connection,
+    since: Instant,
+}
+
+struct PoolState {
+    idle: VecDeque<IdleConnection>,
+    // Every connection the pool is responsible for: idle, checked out, or being opened.
+    open: usize,
+}
+
+/// A pool of connections to one server, so callers don't pay for a TCP connect and a SCRAM
+/// handshake on every request.
+///
+/// Idle connections are checked with a query before being handed out; broken ones are
+/// dropped and replaced with fresh connections.  Eviction happens lazily, whenever a connection is
+/// checked out or returned.
+pub struct Pool {
+    connect_options: ConnectOptions,
+    options: PoolOptions,
+    state: Mutex<PoolState>,
+    returned: Condvar,
+}
+
+/// A connection checked out of a `Pool`.  It goes back to the pool when dropped.
+pub struct PooledConnection<'a> {
+    pool: &'a Pool,
+    conn: Option<Connection>,
+}
+
+impl Pool {
+    /// Creates a pool and opens its first `min_size` connections.  Every connection is opened with
+    /// `connect_options`.
+    pub fn new(connect_options: ConnectOptions, options: PoolOptions) -> Result<Pool, Error> {
+        if options.min_size > options.max_size {
+            return Err(Error::ServerError(format!("The pool's min_size ({}) is larger than its max_size ({}).", options.min_size, options.max_size)));
+        }
+
+        let pool = Pool {
+            connect_options: connect_options,
+            options: options,
+            state: Mutex::new(PoolState {
+                idle: VecDeque::new(),
+                open: 0,
+            }),
+            returned: Condvar::new(),
+        };
+
+        for _ in 0..pool.options.min_size {
+            let conn = pool.connect()?;
+            let mut state = my_try!(pool.state.lock());
+            state.open += 1;
+            state.idle.push_back(IdleConnection {
+                conn: conn,
+                since: Instant::now(),
+            });
+        }
+
+        Ok(pool)
+    }
+
+    fn connect(&self) -> Result<Connection, Error> {
+        Connection::connect_with(self.connect_options.clone())
+    }
+
+    pub fn connect_options(&self) -> &ConnectOptions {
+        &self.connect_options
+    }
+
+    /// A connection is handed out only if it still answers a SERVER_INFO query, or a NOREPLY_WAIT
+    /// query on servers too old to know about SERVER_INFO, within the checkout timeout.  A closed
+    /// connection is dropped rather than reconnected, which could take longer than the timeout.
+    fn is_healthy(&self, conn: &Connection) -> bool {
+        if conn.is_closed() {
+            return false;
+        }
+
+        let timeout = match self.connect_options.read_timeout {
+            Some(read_timeout) => cmp::min(read_timeout, self.options.checkout_timeout),
+            None => self.options.checkout_timeout,
+        };
+        let (response, expected) = match conn.version() {
+            VersionDummy_Version::V1_0 => (conn.server_info_within(timeout), Response_ResponseType::SERVER_INFO),
+            _ => (conn.noreply_wait_within(timeout), Response_ResponseType::WAIT_COMPLETE),
+        };
+
+        match response {
+            Ok(response) => response.response.response_type == expected,
+            Err(_) => false,
+        }
+    }
+
+    /// Closes connections that have been idle too long, oldest first, down to `min_size`.
+    fn evict_idle(&self, state: &mut PoolState) {
+        let idle_timeout = match self.options.idle_timeout {
+            Some(idle_timeout) => idle_timeout,
+            None => return,
+        };
+
+        while state.open > self.options.min_size {
+            match state.idle.front() {
+                Some(idle) if idle.since.elapsed() >= idle_timeout => {},
+                _ => break,
+            }
+            state.idle.pop_front();
+            state.open -= 1;
+        }
+    }
+
+    /// Checks out a connection: an idle one if there is one that's still alive, or a new one if the
+    /// pool has room.  Otherwise, waits up to `checkout_timeout` for one to be returned.
+    pub fn get(&self) -> Result<PooledConnection, Error> {
+        let deadline = Instant::now() + self.options.checkout_timeout;
+        let mut state = my_try!(self.state.lock());
+
+        loop {
+            self.evict_idle(&mut state);
+
+            // Most recently used first, so the oldest ones get a chance to be evicted.
+            if let Some(idle) = state.idle.pop_back() {
+                // Don't hold the lock during the round trip to the server.
+                drop(state);
+                if self.is_healthy(&idle.conn) {
+                    return Ok(PooledConnection {
+                        pool: self,
+                        conn: Some(idle.conn),
+                    });
+                }
+
+                // The socket is broken; forget about it and make room for a replacement.
+                drop(idle);
+                state = my_try!(self.state.lock());
+                state.open -= 1;
+                continue;
+            }
+
+            if state.open < self.options.max_size {
+                state.open += 1;
+                drop(state);
+
+                return match self.connect() {
+                    Ok(conn) => Ok(PooledConnection {
+                        pool: self,
+                        conn: Some(conn),
+                    }),
+                    Err(error) => {
+                        if let Ok(mut state) = self.state.lock() {
+                            state.open -= 1;
+                        }
+                        self.returned.notify_one();
+
+                        Err(error)
+                    },
+                };
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::PoolTimeout(self.options.checkout_timeout));
+            }
+            state = my_try!(self.returned.wait_timeout(state, deadline - now)).0;
+        }
+    }
+
+    /// Puts a connection back in the pool, unless its socket has already failed.
+    fn release(&self, conn: Connection) {
+        if let Ok(mut state) = self.state.lock() {
+            if conn.is_closed() {
+                state.open -= 1;
+            } else {
+                state.idle.push_back(IdleConnection {
+                    conn: conn,
+                    since: Instant::now(),
+                });
+            }
+            self.evict_idle(&mut state);
+        }
+
+        self.returned.notify_one();
+    }
+}
+
+impl<'a> Deref for PooledConnection<'a> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        // Only taken out in `drop`.
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl<'a> Drop for PooledConnection<'a> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.pool.release(conn);
+        }
+    }
+}
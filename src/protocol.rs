@@ -0,0 +1,266 @@
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use error::Error;
+use ql2::{VersionDummy_Protocol, VersionDummy_Version};
+use reql::query::Query;
+use reql::response::Response;
+use scram::{ClientFinal, ClientFirst, ServerFinal, ServerFirst};
+use serde_json::{self, Value};
+use std::collections::BTreeMap;
+use std::u32;
+
+// The pieces of the wire protocol that don't care how bytes get to and from the server, shared by
+// the blocking and the async connections.
+
+const SUB_PROTOCOL_VERSION: i64 = 0;
+
+/// Every response starts with a header holding the query token (8 bytes) and the length of the
+/// response (4 bytes).
+pub const HEADER_LEN: usize = 12;
+
+/// What servers older than 2.3 answer when they are sent the V1_0 magic number, right before they
+/// close the connection.
+const UNSUPPORTED_VERSION: &str = "ERROR: Received an unsupported protocol version.";
+
+/// Which version of the handshake to open connections with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HandshakeVersion {
+    /// The SCRAM-SHA-256 handshake spoken by RethinkDB 2.3 and later.
+    V1_0,
+    /// The auth key handshake spoken by RethinkDB 2.0 up to 2.2.
+    V0_4,
+    /// The auth key handshake spoken by RethinkDB 1.13 up to 1.16.
+    V0_3,
+    /// Tries V1_0 first, and falls back to V0_4 on a new connection if the server turns it down.
+    Negotiate,
+}
+
+impl HandshakeVersion {
+    /// The version to open the first connection attempt with.
+    pub fn first_attempt(&self) -> VersionDummy_Version {
+        match *self {
+            HandshakeVersion::V0_3 => VersionDummy_Version::V0_3,
+            HandshakeVersion::V0_4 => VersionDummy_Version::V0_4,
+            HandshakeVersion::V1_0 | HandshakeVersion::Negotiate => VersionDummy_Version::V1_0,
+        }
+    }
+
+    /// The version to retry with after `error`, if any.
+    pub fn fallback(&self, error: &Error) -> Option<VersionDummy_Version> {
+        match (*self, error) {
+            (HandshakeVersion::Negotiate, &Error::UnsupportedVersion(_)) => Some(VersionDummy_Version::V0_4),
+            _ => None,
+        }
+    }
+}
+
+/// The response returned by V1_0 of the RethinkDB handshake protocol, after a protocol version has
+/// been successfully set.
+#[derive(Debug,Deserialize)]
+pub struct ProtocolSuccessResponse {
+    success: bool,
+    min_protocol_version: i64,
+    max_protocol_version: i64,
+    server_version: String,
+}
+
+#[derive(Debug,Deserialize)]
+pub struct ServerSuccessResponse {
+    pub authentication: String,
+    success: bool,
+}
+
+#[derive(Deserialize)]
+struct ServerErrorResponse {
+    error: String,
+    error_code: i64,
+    success: bool,
+}
+
+pub struct QueryResponse {
+    pub query_token: u64,
+    pub length: u32,
+    pub response: Response,
+}
+
+/// Handshake messages are JSON objects terminated by a NULL byte.
+fn null_terminated(message: &BTreeMap<String, Value>) -> Result<Vec<u8>, Error> {
+    let mut bytes = my_try!(serde_json::to_vec(message));
+    bytes.push(0);
+
+    Ok(bytes)
+}
+
+/// The magic number the client opens every connection with.
+pub fn version_number(version: u32) -> Vec<u8> {
+    let mut bytes = vec![];
+    // Writing to a Vec can't fail.
+    bytes.write_u32::<LittleEndian>(version).unwrap();
+
+    bytes
+}
+
+pub fn parse_protocol_response(resp: &str) -> Result<ProtocolSuccessResponse, Error> {
+    match serde_json::from_str::<ProtocolSuccessResponse>(resp) {
+        Ok(obj) => if obj.success {
+            Ok(obj)
+        } else {
+            // Should never happen, but better to have the check than not.
+            Err(Error::ServerError("Received a success response from RethinkDB with success = false.".to_owned()))
+        },
+        Err(_) => Err(plain_text_error(resp)),
+    }
+}
+
+/// Turns the plain text errors servers send during the handshake into an `Error`.
+fn plain_text_error(resp: &str) -> Error {
+    if resp.starts_with(UNSUPPORTED_VERSION) {
+        Error::UnsupportedVersion(resp.trim().to_owned())
+    } else {
+        Error::ServerError(resp.trim().to_owned())
+    }
+}
+
+/// Builds the whole client side of the V0_3 and V0_4 handshakes: the magic number, the auth key
+/// preceded by its length, then the magic number for the JSON protocol.
+pub fn legacy_handshake_message(version: VersionDummy_Version, auth_key: &str) -> Result<Vec<u8>, Error> {
+    let len = auth_key.as_bytes().len();
+    if len > (u32::MAX as usize) {
+        return Err(Error::ServerError("The auth key is too long.".to_owned()));
+    }
+
+    let mut bytes = version_number(version as u32);
+    my_try!(bytes.write_u32::<LittleEndian>(len as u32));
+    bytes.extend_from_slice(auth_key.as_bytes());
+    my_try!(bytes.write_u32::<LittleEndian>(VersionDummy_Protocol::JSON as u32));
+
+    Ok(bytes)
+}
+
+/// The server answers the V0_3 and V0_4 handshakes with `SUCCESS`, or with an error message.
+pub fn parse_legacy_response(resp: &str) -> Result<(), Error> {
+    if resp == "SUCCESS" {
+        Ok(())
+    } else if resp.starts_with("ERROR: Incorrect authorization key") {
+        Err(Error::ReqlAuthError)
+    } else {
+        Err(plain_text_error(resp))
+    }
+}
+
+/// Builds the first client handshake response with authentication.  Should send something like:
+///
+/// ```json
+/// {
+///   "authentication": "n,,n=user,r=rOprNGfwEbeRWgbNEkqO",
+///   "authentication_method": "SCRAM-SHA-256",
+///   "protocol_version": 0
+/// }
+/// ```
+pub fn client_first_message<'a>(user: &'a str, password: &'a str) -> Result<(ServerFirst<'a>, Vec<u8>), Error> {
+    let client_first = my_try!(ClientFirst::new(user, password, None));
+    let (server_first, auth) = client_first.client_first();
+    let mut message = BTreeMap::new();
+    message.insert("authentication".to_owned(), Value::String(auth));
+    let method = "SCRAM-SHA-256".to_owned();
+    message.insert("authentication_method".to_owned(), Value::String(method));
+    message.insert("protocol_version".to_owned(), Value::from(SUB_PROTOCOL_VERSION));
+
+    Ok((server_first, null_terminated(&message)?))
+}
+
+/// Parses messages from the server, as defined for the RethinkDB handshake in
+/// https://rethinkdb.com/docs/writing-drivers/
+pub fn parse_server_message(resp: &str) -> Result<ServerSuccessResponse, Error> {
+    match serde_json::from_str::<ServerSuccessResponse>(resp) {
+        Ok(success_obj) => if success_obj.success {
+            Ok(success_obj)
+        } else {
+            // Should never happen, but better to have the check than not.
+            Err(Error::ServerError("Received a success response from RethinkDB with success = false.".to_owned()))
+        },
+        Err(_) => match serde_json::from_str::<ServerErrorResponse>(resp) {
+            Ok(error_obj) => if !error_obj.success {
+                // An error code within [10, 20] is defined to return a ReqlAuthError.
+                if error_obj.error_code >= 10 && error_obj.error_code <= 20 {
+                    Err(Error::ReqlAuthError)
+                } else {
+                    Err(Error::ServerError(error_obj.error))
+                }
+            } else {
+                // Should never happen, but better to have the check than not.
+                Err(Error::ServerError("Received an error response from RethinkDB with success = true.".to_owned()))
+            },
+            // We don't have either a success or an error response.  Very weird.
+            Err(error) => Err(Error::ServerError(format!("{}", error))),
+        }
+    }
+}
+
+/// Builds the final client message in the authentication handshake.  Should look like:
+///
+/// ```json
+/// {
+///   "authentication": "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+/// }
+/// ```
+pub fn client_final_message(client_final: ClientFinal) -> Result<(ServerFinal, Vec<u8>), Error> {
+    let (server_final, auth) = client_final.client_final();
+    let mut message = BTreeMap::new();
+    message.insert("authentication".to_owned(), Value::String(auth));
+
+    Ok((server_final, null_terminated(&message)?))
+}
+
+/// The most bytes a serialized query can take up, since its length has to fit in the frame header.
+pub const MAX_QUERY_LEN: usize = u32::MAX as usize;
+
+/// Builds a query frame: the token, the length of the serialized query, then the query itself.
+/// Queries longer than `max_len` bytes are turned down; connections pass `MAX_QUERY_LEN`.
+pub fn encode_query(token: u64, query: &Query, max_len: usize) -> Result<Vec<u8>, Error> {
+    let query = my_try!(serde_json::to_string(query));
+    let len = query.as_bytes().len();
+    if len > max_len {
+        return Err(Error::QueryTooLarge(len));
+    }
+
+    let mut frame = Vec::with_capacity(HEADER_LEN + len);
+    my_try!(frame.write_u64::<LittleEndian>(token));
+    my_try!(frame.write_u32::<LittleEndian>(len as u32));
+    frame.extend_from_slice(query.as_bytes());
+
+    Ok(frame)
+}
+
+/// Splits a response header into the query token and the length of the response that follows.
+pub fn decode_header(header: &[u8]) -> (u64, u32) {
+    (LittleEndian::read_u64(&header[..8]), LittleEndian::read_u32(&header[8..HEADER_LEN]))
+}
+
+/// Takes the first complete frame off the front of `buffer`, if there is one.
+pub fn take_frame(buffer: &mut Vec<u8>) -> Option<(u64, Vec<u8>)> {
+    if buffer.len() < HEADER_LEN {
+        return None;
+    }
+
+    let (token, len) = decode_header(&buffer[..HEADER_LEN]);
+    let end = HEADER_LEN + len as usize;
+    if buffer.len() < end {
+        return None;
+    }
+
+    let recv = buffer[HEADER_LEN..end].to_vec();
+    buffer.drain(..end);
+
+    Some((token, recv))
+}
+
+pub fn decode_response(token: u64, recv: &[u8]) -> Result<QueryResponse, Error> {
+    let json = my_try!(serde_json::from_slice::<Value>(recv));
+    let response = Response::from_json(json)?;
+
+    Ok(QueryResponse {
+        query_token: token,
+        length: recv.len() as u32,
+        response: response,
+    })
+}
@@ -0,0 +1,110 @@
+use std::cell::Cell;
+use super::super::ql2::Term_TermType;
+use super::term::{IntoTerm, Term};
+
+// The variable ids of the functions being built on this thread.  Ids are handed out while a
+// function's body is built and given back afterwards, so sibling functions reuse the same ids and
+// only nested functions need new ones.  That keeps the ids (and so the serialized query) the same
+// every time a query is built.
+thread_local!(static NEXT_VAR: Cell<u64> = Cell::new(1));
+
+/// Builds a FUNC term taking `arity` arguments, whose body is whatever `body` returns when given the
+/// VAR terms for them.
+///
+/// ```text
+/// [FUNC, [[MAKE_ARRAY, [1, 2]], <body>]]
+/// ```
+fn build<F: FnOnce(Vec<Term>) -> Term>(arity: u64, body: F) -> Term {
+    let first = NEXT_VAR.with(|next| {
+        let first = next.get();
+        next.set(first + arity);
+
+        first
+    });
+    let ids = (first..first + arity).collect::<Vec<u64>>();
+    let vars = ids.iter().map(|&id| Term::call(Term_TermType::VAR, vec![id.into_term()])).collect();
+    let body = body(vars);
+    NEXT_VAR.with(|next| next.set(first));
+
+    Term::call(Term_TermType::FUNC, vec![ids.into_term(), body])
+}
+
+/// A function of one argument, for methods that only take functions.  Taking the closure directly
+/// (rather than anything `IntoTerm`) is what lets Rust infer its argument types, so callers can
+/// write `|doc| ...` instead of `|doc: Term| ...`.
+pub fn func1<F: FnOnce(Term) -> Term>(function: F) -> Term {
+    build(1, |mut vars| {
+        let a = vars.remove(0);
+
+        function(a)
+    })
+}
+
+pub fn func2<F: FnOnce(Term, Term) -> Term>(function: F) -> Term {
+    build(2, |mut vars| {
+        let a = vars.remove(0);
+        let b = vars.remove(0);
+
+        function(a, b)
+    })
+}
+
+pub fn func3<F: FnOnce(Term, Term, Term) -> Term>(function: F) -> Term {
+    build(3, |mut vars| {
+        let a = vars.remove(0);
+        let b = vars.remove(0);
+        let c = vars.remove(0);
+
+        function(a, b, c)
+    })
+}
+
+/// Rust closures that can be turned into ReQL functions, taking one, two or three terms.  A term
+/// that already is a function (e.g. from `r.js`) counts too.  Rust can't infer the argument types
+/// of closures passed this way, so they have to be spelled out: `|a: Term, b: Term| ...`.
+pub trait Lambda<Args> {
+    fn into_func(self) -> Term;
+}
+
+impl<F: FnOnce(Term) -> Term> Lambda<(Term,)> for F {
+    fn into_func(self) -> Term {
+        func1(self)
+    }
+}
+
+impl<F: FnOnce(Term, Term) -> Term> Lambda<(Term, Term)> for F {
+    fn into_func(self) -> Term {
+        func2(self)
+    }
+}
+
+impl<F: FnOnce(Term, Term, Term) -> Term> Lambda<(Term, Term, Term)> for F {
+    fn into_func(self) -> Term {
+        func3(self)
+    }
+}
+
+impl Lambda<()> for Term {
+    fn into_func(self) -> Term {
+        self
+    }
+}
+
+/// Closures of one argument can go wherever a term can, e.g. to `update` or `merge`, as long as the
+/// argument's type is spelled out.
+impl<F: FnOnce(Term) -> Term> IntoTerm for F {
+    fn into_term(self) -> Term {
+        self.into_func()
+    }
+}
+
+/// Terms using `r.row()` have to be wrapped in a function of one argument before the server will
+/// accept them where a function is expected.  Anything else is left alone.
+pub fn func_wrap<T: IntoTerm>(value: T) -> Term {
+    let term = value.into_term();
+    if !term.as_tree().has_implicit_var() {
+        return term;
+    }
+
+    build(1, |_| term)
+}
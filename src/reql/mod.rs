@@ -1 +1,8 @@
+pub mod func;
+pub mod query;
+pub mod r;
+pub mod response;
+pub mod results;
+pub mod term;
 pub mod tree;
+pub mod types;
@@ -0,0 +1,79 @@
+use protobuf::ProtobufEnum;
+use serde::ser::{Serialize, SerializeSeq, Serializer};
+use std::collections::BTreeMap;
+use super::super::ql2::Query_QueryType;
+use super::tree::Tree;
+
+/// The envelope every message to the server is wrapped in.  A START query serializes to:
+///
+/// ```text
+/// [<query type>, <tree>, <global optargs>]
+/// ```
+///
+/// CONTINUE, STOP, NOREPLY_WAIT and SERVER_INFO carry no term and are sent as `[<query type>]`;
+/// the server matches them to a running query by the token written in the frame header.
+#[derive(Clone)]
+pub struct Query {
+    pub query_type: Query_QueryType,
+    pub term: Option<Tree>,
+    pub global_optargs: BTreeMap<String, Tree>,
+}
+
+impl Query {
+    /// Starts a new query, which is evaluated and returns the first batch of results.
+    pub fn start(term: Tree) -> Query {
+        Query {
+            query_type: Query_QueryType::START,
+            term: Some(term),
+            global_optargs: BTreeMap::new(),
+        }
+    }
+
+    /// Asks for the next batch of a query that returned SUCCESS_PARTIAL.
+    pub fn continue_query() -> Query {
+        Query::without_term(Query_QueryType::CONTINUE)
+    }
+
+    /// Tells the server to stop sending batches for a running query.
+    pub fn stop() -> Query {
+        Query::without_term(Query_QueryType::STOP)
+    }
+
+    /// Waits until every previously sent noreply query has been processed.
+    pub fn noreply_wait() -> Query {
+        Query::without_term(Query_QueryType::NOREPLY_WAIT)
+    }
+
+    /// Asks the server to describe itself.
+    pub fn server_info() -> Query {
+        Query::without_term(Query_QueryType::SERVER_INFO)
+    }
+
+    fn without_term(query_type: Query_QueryType) -> Query {
+        Query {
+            query_type: query_type,
+            term: None,
+            global_optargs: BTreeMap::new(),
+        }
+    }
+}
+
+impl Serialize for Query {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // The optargs object is only meaningful for START, and the server accepts it being left
+        // off entirely.
+        let has_optargs = self.term.is_some() && !self.global_optargs.is_empty();
+        let len = 1 + self.term.is_some() as usize + has_optargs as usize;
+        let mut array = serializer.serialize_seq(Some(len))?;
+        array.serialize_element(&self.query_type.value())?;
+
+        if let Some(ref term) = self.term {
+            array.serialize_element(term)?;
+            if has_optargs {
+                array.serialize_element(&self.global_optargs)?;
+            }
+        }
+
+        array.end()
+    }
+}
@@ -0,0 +1,312 @@
+use super::super::ql2::Term_TermType;
+use super::func::{func_wrap, Lambda};
+use super::term::{IntoTerm, Term};
+
+/// The starting point of every query, for terms that aren't chained on another one.  Use it through
+/// the `r` constant, like in the other drivers:
+///
+/// ```rust
+/// # extern crate rethinkdb;
+/// # use rethinkdb::r;
+/// # fn main() {
+/// r.db("app").table("users").get("ada")
+/// # ;
+/// # }
+/// ```
+#[derive(Clone, Copy)]
+pub struct R;
+
+#[allow(non_upper_case_globals)]
+pub const r: R = R;
+
+impl R {
+    /// Turns a plain value into a term, so it can be chained on.
+    pub fn expr<T: IntoTerm>(&self, value: T) -> Term {
+        value.into_term()
+    }
+
+    /// Builds a term of any type, for the ones without a method of their own.
+    pub fn call(&self, head: Term_TermType, args: Vec<Term>) -> Term {
+        Term::call(head, args)
+    }
+
+    // Databases and tables
+
+    pub fn db<T: IntoTerm>(&self, name: T) -> Term {
+        Term::call(Term_TermType::DB, vec![name.into_term()])
+    }
+
+    pub fn db_create<T: IntoTerm>(&self, name: T) -> Term {
+        Term::call(Term_TermType::DB_CREATE, vec![name.into_term()])
+    }
+
+    pub fn db_drop<T: IntoTerm>(&self, name: T) -> Term {
+        Term::call(Term_TermType::DB_DROP, vec![name.into_term()])
+    }
+
+    pub fn db_list(&self) -> Term {
+        Term::call(Term_TermType::DB_LIST, vec![])
+    }
+
+    /// A table in the connection's default database.
+    pub fn table<T: IntoTerm>(&self, name: T) -> Term {
+        Term::call(Term_TermType::TABLE, vec![name.into_term()])
+    }
+
+    pub fn table_create<T: IntoTerm>(&self, name: T) -> Term {
+        Term::call(Term_TermType::TABLE_CREATE, vec![name.into_term()])
+    }
+
+    pub fn table_drop<T: IntoTerm>(&self, name: T) -> Term {
+        Term::call(Term_TermType::TABLE_DROP, vec![name.into_term()])
+    }
+
+    pub fn table_list(&self) -> Term {
+        Term::call(Term_TermType::TABLE_LIST, vec![])
+    }
+
+    /// Grants `permissions` to `user` globally.
+    pub fn grant<U: IntoTerm, P: IntoTerm>(&self, user: U, permissions: P) -> Term {
+        Term::call(Term_TermType::GRANT, vec![user.into_term(), permissions.into_term()])
+    }
+
+    // Documents and values
+
+    /// The document currently being looked at, in predicates like `filter`'s.
+    pub fn row(&self) -> Term {
+        Term::call(Term_TermType::IMPLICIT_VAR, vec![])
+    }
+
+    /// Builds an object from alternating keys and values.
+    pub fn object<T: IntoTerm>(&self, keys_and_values: Vec<T>) -> Term {
+        Term::call(Term_TermType::OBJECT, keys_and_values.into_iter().map(IntoTerm::into_term).collect())
+    }
+
+    /// Splices the elements of `array` into the arguments of the term it's passed to.
+    pub fn args<T: IntoTerm>(&self, array: T) -> Term {
+        Term::call(Term_TermType::ARGS, vec![array.into_term()])
+    }
+
+    /// Replaces a whole field in `update` and `merge`, rather than merging into it.
+    pub fn literal<T: IntoTerm>(&self, value: T) -> Term {
+        Term::call(Term_TermType::LITERAL, vec![value.into_term()])
+    }
+
+    pub fn json<T: IntoTerm>(&self, text: T) -> Term {
+        Term::call(Term_TermType::JSON, vec![text.into_term()])
+    }
+
+    pub fn binary<T: IntoTerm>(&self, data: T) -> Term {
+        Term::call(Term_TermType::BINARY, vec![data.into_term()])
+    }
+
+    /// Orders by `key` ascending in `order_by`: a field name, a function, or a term using
+    /// `r.row()`.
+    pub fn asc<T: IntoTerm>(&self, key: T) -> Term {
+        Term::call(Term_TermType::ASC, vec![func_wrap(key)])
+    }
+
+    /// Like `asc`, descending.
+    pub fn desc<T: IntoTerm>(&self, key: T) -> Term {
+        Term::call(Term_TermType::DESC, vec![func_wrap(key)])
+    }
+
+    /// Lower than any other value, for `between` ranges without a lower bound.
+    pub fn minval(&self) -> Term {
+        Term::call(Term_TermType::MINVAL, vec![])
+    }
+
+    /// Higher than any other value, for `between` ranges without an upper bound.
+    pub fn maxval(&self) -> Term {
+        Term::call(Term_TermType::MAXVAL, vec![])
+    }
+
+    // Control structures
+
+    /// `branch(test, then, else)`, or more tests and values for an if/else if chain.
+    pub fn branch<T: IntoTerm>(&self, tests_and_values: Vec<T>) -> Term {
+        Term::call(Term_TermType::BRANCH, tests_and_values.into_iter().map(IntoTerm::into_term).collect())
+    }
+
+    /// Turns a closure into a ReQL function, for places that take a function but whose argument
+    /// isn't a closure, like `r.do_`'s.
+    pub fn func<A, F: Lambda<A>>(&self, function: F) -> Term {
+        function.into_func()
+    }
+
+    /// Calls `function` with `args`.
+    pub fn do_<T: IntoTerm, A, F: Lambda<A>>(&self, args: Vec<T>, function: F) -> Term {
+        let mut all = vec![function.into_func()];
+        all.extend(args.into_iter().map(IntoTerm::into_term));
+
+        Term::call(Term_TermType::FUNCALL, all)
+    }
+
+    pub fn error<T: IntoTerm>(&self, message: T) -> Term {
+        Term::call(Term_TermType::ERROR, vec![message.into_term()])
+    }
+
+    pub fn js<T: IntoTerm>(&self, code: T) -> Term {
+        Term::call(Term_TermType::JAVASCRIPT, vec![code.into_term()])
+    }
+
+    pub fn http<T: IntoTerm>(&self, url: T) -> Term {
+        Term::call(Term_TermType::HTTP, vec![url.into_term()])
+    }
+
+    pub fn uuid(&self) -> Term {
+        Term::call(Term_TermType::UUID, vec![])
+    }
+
+    /// A random float between 0 and 1.
+    pub fn random(&self) -> Term {
+        Term::call(Term_TermType::RANDOM, vec![])
+    }
+
+    /// The integers from 0 up to, but not including, `end`.
+    pub fn range<T: IntoTerm>(&self, end: T) -> Term {
+        Term::call(Term_TermType::RANGE, vec![end.into_term()])
+    }
+
+    pub fn union<T: IntoTerm>(&self, sequences: Vec<T>) -> Term {
+        Term::call(Term_TermType::UNION, sequences.into_iter().map(IntoTerm::into_term).collect())
+    }
+
+    pub fn and<T: IntoTerm>(&self, terms: Vec<T>) -> Term {
+        Term::call(Term_TermType::AND, terms.into_iter().map(IntoTerm::into_term).collect())
+    }
+
+    pub fn or<T: IntoTerm>(&self, terms: Vec<T>) -> Term {
+        Term::call(Term_TermType::OR, terms.into_iter().map(IntoTerm::into_term).collect())
+    }
+
+    /// Waits for every table in the default database to be ready.
+    pub fn wait(&self) -> Term {
+        Term::call(Term_TermType::WAIT, vec![])
+    }
+
+    // Dates and times
+
+    pub fn now(&self) -> Term {
+        Term::call(Term_TermType::NOW, vec![])
+    }
+
+    /// Midnight on a date, in `timezone` (e.g. `"Z"` or `"-07:00"`).
+    pub fn time<Y: IntoTerm, M: IntoTerm, D: IntoTerm, Z: IntoTerm>(&self, year: Y, month: M, day: D, timezone: Z) -> Term {
+        Term::call(Term_TermType::TIME, vec![year.into_term(), month.into_term(), day.into_term(), timezone.into_term()])
+    }
+
+    pub fn epoch_time<T: IntoTerm>(&self, seconds: T) -> Term {
+        Term::call(Term_TermType::EPOCH_TIME, vec![seconds.into_term()])
+    }
+
+    pub fn iso8601<T: IntoTerm>(&self, text: T) -> Term {
+        Term::call(Term_TermType::ISO8601, vec![text.into_term()])
+    }
+
+    pub fn monday(&self) -> Term {
+        Term::call(Term_TermType::MONDAY, vec![])
+    }
+
+    pub fn tuesday(&self) -> Term {
+        Term::call(Term_TermType::TUESDAY, vec![])
+    }
+
+    pub fn wednesday(&self) -> Term {
+        Term::call(Term_TermType::WEDNESDAY, vec![])
+    }
+
+    pub fn thursday(&self) -> Term {
+        Term::call(Term_TermType::THURSDAY, vec![])
+    }
+
+    pub fn friday(&self) -> Term {
+        Term::call(Term_TermType::FRIDAY, vec![])
+    }
+
+    pub fn saturday(&self) -> Term {
+        Term::call(Term_TermType::SATURDAY, vec![])
+    }
+
+    pub fn sunday(&self) -> Term {
+        Term::call(Term_TermType::SUNDAY, vec![])
+    }
+
+    pub fn january(&self) -> Term {
+        Term::call(Term_TermType::JANUARY, vec![])
+    }
+
+    pub fn february(&self) -> Term {
+        Term::call(Term_TermType::FEBRUARY, vec![])
+    }
+
+    pub fn march(&self) -> Term {
+        Term::call(Term_TermType::MARCH, vec![])
+    }
+
+    pub fn april(&self) -> Term {
+        Term::call(Term_TermType::APRIL, vec![])
+    }
+
+    pub fn may(&self) -> Term {
+        Term::call(Term_TermType::MAY, vec![])
+    }
+
+    pub fn june(&self) -> Term {
+        Term::call(Term_TermType::JUNE, vec![])
+    }
+
+    pub fn july(&self) -> Term {
+        Term::call(Term_TermType::JULY, vec![])
+    }
+
+    pub fn august(&self) -> Term {
+        Term::call(Term_TermType::AUGUST, vec![])
+    }
+
+    pub fn september(&self) -> Term {
+        Term::call(Term_TermType::SEPTEMBER, vec![])
+    }
+
+    pub fn october(&self) -> Term {
+        Term::call(Term_TermType::OCTOBER, vec![])
+    }
+
+    pub fn november(&self) -> Term {
+        Term::call(Term_TermType::NOVEMBER, vec![])
+    }
+
+    pub fn december(&self) -> Term {
+        Term::call(Term_TermType::DECEMBER, vec![])
+    }
+
+    // Geospatial
+
+    pub fn point<T: IntoTerm, U: IntoTerm>(&self, longitude: T, latitude: U) -> Term {
+        Term::call(Term_TermType::POINT, vec![longitude.into_term(), latitude.into_term()])
+    }
+
+    pub fn line<T: IntoTerm>(&self, points: Vec<T>) -> Term {
+        Term::call(Term_TermType::LINE, points.into_iter().map(IntoTerm::into_term).collect())
+    }
+
+    pub fn polygon<T: IntoTerm>(&self, points: Vec<T>) -> Term {
+        Term::call(Term_TermType::POLYGON, points.into_iter().map(IntoTerm::into_term).collect())
+    }
+
+    pub fn circle<T: IntoTerm, U: IntoTerm>(&self, center: T, radius: U) -> Term {
+        Term::call(Term_TermType::CIRCLE, vec![center.into_term(), radius.into_term()])
+    }
+
+    pub fn geojson<T: IntoTerm>(&self, geojson: T) -> Term {
+        Term::call(Term_TermType::GEOJSON, vec![geojson.into_term()])
+    }
+
+    pub fn distance<T: IntoTerm, U: IntoTerm>(&self, a: T, b: U) -> Term {
+        Term::call(Term_TermType::DISTANCE, vec![a.into_term(), b.into_term()])
+    }
+
+    pub fn intersects<T: IntoTerm, U: IntoTerm>(&self, a: T, b: U) -> Term {
+        Term::call(Term_TermType::INTERSECTS, vec![a.into_term(), b.into_term()])
+    }
+}
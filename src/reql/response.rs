@@ -0,0 +1,179 @@
+use protobuf::ProtobufEnum;
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value};
+use super::super::error::Error;
+use super::super::ql2::{Response_ErrorType, Response_ResponseNote, Response_ResponseType};
+
+/// A decoded server response.  On the wire it looks like:
+///
+/// ```json
+/// {
+///   "t": <response type>,
+///   "r": [<result>, ...],
+///   "e": <error type>,
+///   "n": [<response note>, ...],
+///   "b": <backtrace>,
+///   "p": <profile>
+/// }
+/// ```
+///
+/// Only `t` and `r` are always present.  `e` and `b` come back with errors, `n` with changefeeds,
+/// and `p` only when the query was run with the `profile` optarg.
+pub struct Response {
+    pub response_type: Response_ResponseType,
+    pub results: Vec<Value>,
+    pub error_type: Option<Response_ErrorType>,
+    pub notes: Vec<Response_ResponseNote>,
+    pub backtrace: Option<Value>,
+    pub profile: Option<Value>,
+}
+
+/// What kind of changefeed a query returned, as told by the notes of its responses.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FeedType {
+    /// Changes to a table or a selection of it.
+    Sequence,
+    /// Changes to a single document, or a value computed from one.
+    Atom,
+    /// Changes to the results of `order_by(...).limit(...)`, which come with offsets.
+    OrderByLimit,
+    /// Several feeds merged with `union`.
+    Unioned,
+}
+
+/// A response as it comes off the wire, before its numeric fields are checked.
+#[derive(Deserialize)]
+struct RawResponse {
+    t: i32,
+    #[serde(default)]
+    r: Vec<Value>,
+    e: Option<i32>,
+    #[serde(default)]
+    n: Vec<i32>,
+    b: Option<Value>,
+    p: Option<Value>,
+}
+
+/// Turns a numeric field into the protobuf enum it stands for.
+fn decode_enum<T: ProtobufEnum>(n: i32, field: &str) -> Result<T, Error> {
+    match T::from_i32(n) {
+        Some(value) => Ok(value),
+        None => Err(Error::ServerError(format!("Unknown value for \"{}\" in response: {}", field, n))),
+    }
+}
+
+/// Decodes a result into whatever type the caller asked for.
+pub fn decode<T: DeserializeOwned>(json: Value) -> Result<T, Error> {
+    serde_json::from_value(json).map_err(|error| Error::DecodeError(format!("{}", error)))
+}
+
+impl Response {
+    pub fn from_json(json: Value) -> Result<Response, Error> {
+        let raw = match serde_json::from_value::<RawResponse>(json) {
+            Ok(raw) => raw,
+            Err(error) => return Err(Error::ServerError(format!("Malformed response: {}", error))),
+        };
+
+        let error_type = match raw.e {
+            Some(e) => Some(decode_enum::<Response_ErrorType>(e, "e")?),
+            None => None,
+        };
+        let mut notes = vec![];
+        for note in raw.n {
+            notes.push(decode_enum::<Response_ResponseNote>(note, "n")?);
+        }
+
+        Ok(Response {
+            response_type: decode_enum::<Response_ResponseType>(raw.t, "t")?,
+            results: raw.r,
+            error_type: error_type,
+            notes: notes,
+            backtrace: raw.b,
+            profile: raw.p,
+        })
+    }
+
+    /// The kind of changefeed the query is, if it is one.
+    pub fn feed_type(&self) -> Option<FeedType> {
+        self.notes.iter().filter_map(|note| match *note {
+            Response_ResponseNote::SEQUENCE_FEED => Some(FeedType::Sequence),
+            Response_ResponseNote::ATOM_FEED => Some(FeedType::Atom),
+            Response_ResponseNote::ORDER_BY_LIMIT_FEED => Some(FeedType::OrderByLimit),
+            Response_ResponseNote::UNIONED_FEED => Some(FeedType::Unioned),
+            Response_ResponseNote::INCLUDES_STATES => None,
+        }).next()
+    }
+
+    /// Whether the changefeed sends state changes along with the changes themselves.
+    pub fn includes_states(&self) -> bool {
+        self.notes.contains(&Response_ResponseNote::INCLUDES_STATES)
+    }
+
+    /// SUCCESS_PARTIAL means there are more results waiting on the server, to be fetched with a
+    /// CONTINUE query.
+    pub fn is_partial(&self) -> bool {
+        self.response_type == Response_ResponseType::SUCCESS_PARTIAL
+    }
+
+    pub fn is_error(&self) -> bool {
+        match self.response_type {
+            Response_ResponseType::CLIENT_ERROR |
+            Response_ResponseType::COMPILE_ERROR |
+            Response_ResponseType::RUNTIME_ERROR => true,
+            _ => false,
+        }
+    }
+
+    /// For error responses, builds the matching `Error`.  The message is the first (and only)
+    /// result.
+    pub fn to_error(&self) -> Option<Error> {
+        if !self.is_error() {
+            return None;
+        }
+
+        let message = match self.results.first() {
+            Some(&Value::String(ref message)) => message.clone(),
+            Some(other) => format!("{}", other),
+            None => "The server returned an error without a message.".to_owned(),
+        };
+
+        Some(match self.response_type {
+            Response_ResponseType::CLIENT_ERROR => Error::ReqlClientError(message),
+            Response_ResponseType::COMPILE_ERROR => Error::ReqlCompileError(message),
+            _ => Error::ReqlRuntimeError(self.error_type, message),
+        })
+    }
+
+    /// The results a cursor should yield for this response, or the error it carries.  An atom is
+    /// yielded as a single result, even if it is an array.
+    pub fn into_batch(self) -> Result<Vec<Value>, Error> {
+        if let Some(error) = self.to_error() {
+            return Err(error);
+        }
+
+        match self.response_type {
+            Response_ResponseType::SUCCESS_ATOM => Ok(self.results.into_iter().take(1).collect()),
+            _ => Ok(self.results),
+        }
+    }
+
+    /// The whole result of the query: the atom for SUCCESS_ATOM, or the results as an array for
+    /// sequences.  Answers to SERVER_INFO and the like are their only result.
+    pub fn into_value(self) -> Result<Value, Error> {
+        if let Some(error) = self.to_error() {
+            return Err(error);
+        }
+
+        match self.response_type {
+            Response_ResponseType::SUCCESS_SEQUENCE |
+            Response_ResponseType::SUCCESS_PARTIAL => Ok(Value::Array(self.results)),
+            _ => Ok(self.results.into_iter().next().unwrap_or(Value::Null)),
+        }
+    }
+
+    /// Decodes the whole result of the query, as returned by `into_value`.  Pseudo-types are left
+    /// as the server sent them.
+    pub fn into_result<T: DeserializeOwned>(self) -> Result<T, Error> {
+        decode(self.into_value()?)
+    }
+}
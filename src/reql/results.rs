@@ -0,0 +1,299 @@
+use error::Error;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use super::response;
+use super::types::Binary;
+
+// What the server answers to joins, writes and administration queries, for decoding with
+// `Connection::run`.
+
+/// An entry of `changes`, `config_changes` or `status_changes`, or an event of a changefeed: the
+/// value before and after the query.  Either side is `None` when the query created or removed the
+/// thing described.
+///
+/// The other fields are only there on changefeeds asking for them, with `include_types`,
+/// `include_states` and `include_offsets`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Change<T> {
+    pub old_val: Option<T>,
+    pub new_val: Option<T>,
+    #[serde(rename = "type")]
+    pub change_type: Option<ChangeType>,
+    /// Set on the events telling how far along the feed is, instead of the values.
+    pub state: Option<FeedState>,
+    /// Where the documents were and are in the results of an `order_by(...).limit(...)` feed.
+    pub old_offset: Option<u64>,
+    pub new_offset: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeType {
+    Add,
+    Remove,
+    Change,
+    /// A result already there when the feed started, with `include_initial`.
+    Initial,
+    /// An initial result that went away before the feed was done sending them.
+    Uninitial,
+    State,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedState {
+    /// The feed is still sending the initial results.
+    Initializing,
+    /// Every event from now on is a change.
+    Ready,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct DbConfig {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct DbCreated {
+    pub dbs_created: u32,
+    pub config_changes: Vec<Change<DbConfig>>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct DbDropped {
+    pub dbs_dropped: u32,
+    pub tables_dropped: u32,
+    pub config_changes: Vec<Change<DbConfig>>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct ShardConfig {
+    pub primary_replica: String,
+    pub replicas: Vec<String>,
+    #[serde(default)]
+    pub nonvoting_replicas: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct TableConfig {
+    pub id: String,
+    pub name: String,
+    pub db: String,
+    pub primary_key: String,
+    pub shards: Vec<ShardConfig>,
+    /// `"majority"`, `"single"`, or a list of per-replica-tag settings.
+    pub write_acks: Value,
+    pub durability: String,
+    #[serde(default)]
+    pub indexes: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct TableCreated {
+    pub tables_created: u32,
+    pub config_changes: Vec<Change<TableConfig>>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct TableDropped {
+    pub tables_dropped: u32,
+    pub config_changes: Vec<Change<TableConfig>>,
+}
+
+/// How available a table is, from least to most.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct TableReadiness {
+    pub ready_for_outdated_reads: bool,
+    pub ready_for_reads: bool,
+    pub ready_for_writes: bool,
+    pub all_replicas_ready: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct ReplicaStatus {
+    pub server: String,
+    /// e.g. `ready`, `backfilling` or `disconnected`.
+    pub state: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct ShardStatus {
+    pub primary_replicas: Vec<String>,
+    pub replicas: Vec<ReplicaStatus>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct TableStatus {
+    pub id: String,
+    pub name: String,
+    pub db: String,
+    pub status: TableReadiness,
+    pub shards: Vec<ShardStatus>,
+    pub raft_leader: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Waited {
+    pub ready: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Reconfigured {
+    pub reconfigured: u32,
+    pub config_changes: Vec<Change<TableConfig>>,
+    pub status_changes: Vec<Change<TableStatus>>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Rebalanced {
+    pub rebalanced: u32,
+    pub status_changes: Vec<Change<TableStatus>>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Synced {
+    pub synced: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct IndexCreated {
+    pub created: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct IndexDropped {
+    pub dropped: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct IndexRenamed {
+    pub renamed: u32,
+}
+
+/// An element of what `index_status` and `index_wait` return.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct IndexStatus {
+    pub index: String,
+    pub ready: bool,
+    /// How far along the index is being built, between 0 and 1.  Only there while it isn't ready.
+    pub progress: Option<f64>,
+    /// The index function, in a form `index_create_from` takes back.
+    pub function: Binary,
+    pub multi: bool,
+    pub geo: bool,
+    /// Whether the index was built by an older server, and has to be recreated to be used.  Servers
+    /// older than 2.1 don't say, and are taken to be up to date.
+    #[serde(default)]
+    pub outdated: bool,
+    /// The ReQL query creating the index, as text.  Servers older than 2.1 don't send it.
+    pub query: Option<String>,
+}
+
+/// An element of what `inner_join`, `outer_join` and `eq_join` return.  Decode `outer_join`
+/// results with an `Option` on the right, for elements without a match.  Join results also decode
+/// straight into `(L, R)` tuples.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Joined<L, R> {
+    pub left: L,
+    pub right: R,
+}
+
+impl<L, R> From<Joined<L, R>> for (L, R) {
+    fn from(joined: Joined<L, R>) -> (L, R) {
+        (joined.left, joined.right)
+    }
+}
+
+/// Turns `json`, or each of its elements if it is an array, into a `[left, right]` pair where it
+/// is a join result, so it can be decoded into a tuple.  `right` is `null` for `outer_join`
+/// elements without a match.
+pub fn joined_pairs(json: Value) -> Value {
+    match json {
+        Value::Array(array) => Value::Array(array.into_iter().map(joined_pair).collect()),
+        json => joined_pair(json),
+    }
+}
+
+/// Whether `json`, or any of its elements if it is an array, looks like a join result.
+pub fn has_joined(json: &Value) -> bool {
+    match json {
+        &Value::Array(ref array) => array.iter().any(is_joined),
+        json => is_joined(json),
+    }
+}
+
+fn is_joined(json: &Value) -> bool {
+    match json {
+        &Value::Object(ref object) => {
+            object.contains_key("left") && object.keys().all(|key| key == "left" || key == "right")
+        },
+        _ => false,
+    }
+}
+
+fn joined_pair(json: Value) -> Value {
+    if !is_joined(&json) {
+        return json;
+    }
+
+    match json {
+        Value::Object(mut object) => {
+            let left = object.remove("left").unwrap_or(Value::Null);
+            let right = object.remove("right").unwrap_or(Value::Null);
+            Value::Array(vec![left, right])
+        },
+        json => json,
+    }
+}
+
+/// What `insert`, `update`, `replace` and `delete` return, with the changed documents decoded into
+/// `T`.  The server reports documents it failed to write in `errors` rather than failing the whole
+/// query; `check` turns those into an error.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct WriteResult<T = Value> {
+    #[serde(default)]
+    pub inserted: u32,
+    #[serde(default)]
+    pub replaced: u32,
+    #[serde(default)]
+    pub unchanged: u32,
+    #[serde(default)]
+    pub skipped: u32,
+    #[serde(default)]
+    pub deleted: u32,
+    #[serde(default)]
+    pub errors: u32,
+    /// The reason the first document in `errors` couldn't be written.
+    pub first_error: Option<String>,
+    /// The primary keys the server made up for inserted documents without one.
+    #[serde(default)]
+    pub generated_keys: Vec<String>,
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    /// Only there with `return_changes`.
+    #[serde(default = "Vec::new")]
+    pub changes: Vec<Change<T>>,
+}
+
+impl WriteResult {
+    /// Fails with `Error::WriteError` if any document couldn't be written.  The error carries the
+    /// whole result, so the documents that did get written can still be accounted for.
+    pub fn check(self) -> Result<WriteResult, Error> {
+        if self.errors == 0 {
+            return Ok(self);
+        }
+
+        Err(Error::WriteError(Box::new(self)))
+    }
+}
+
+impl<T: DeserializeOwned> WriteResult<T> {
+    /// Decodes the result of a write, failing like `check` does if any document couldn't be
+    /// written.
+    pub fn checked(json: Value) -> Result<WriteResult<T>, Error> {
+        response::decode::<WriteResult>(json.clone())?.check()?;
+
+        response::decode(json)
+    }
+}
@@ -0,0 +1,1203 @@
+use error::Error;
+use serde::Serialize;
+use serde_json::{self, Value};
+use std::collections::BTreeMap;
+use super::super::ql2::Term_TermType;
+use super::func::{func1, func2, func3, func_wrap, Lambda};
+use super::tree::{IntoTree, SelectionType, Tree};
+use super::types::{Binary, Field, Geometry, Time};
+
+/// A ReQL term under construction, built up by chaining calls that each wrap the term so far in a
+/// new one:
+///
+/// ```rust
+/// # extern crate rethinkdb;
+/// # use rethinkdb::r;
+/// # fn main() {
+/// r.db("app").table("users").filter(|user| user.get_field("age").gt(18)).limit(10)
+/// # ;
+/// # }
+/// ```
+///
+/// Calls are checked against the number of arguments their term takes.  A bad call doesn't stop
+/// the chain; the first error is kept and reported when the query is run.  Writes are the exception:
+/// they fail as soon as they are chained on something the server can't write to.
+#[derive(Clone, Debug)]
+pub struct Term {
+    tree: Tree,
+    error: Option<String>,
+}
+
+/// Anything that can be an argument to a term: other terms, trees, and plain values.
+pub trait IntoTerm {
+    fn into_term(self) -> Term;
+}
+
+impl IntoTerm for Term {
+    fn into_term(self) -> Term {
+        self
+    }
+}
+
+impl IntoTerm for Tree {
+    fn into_term(self) -> Term {
+        Term {
+            tree: self,
+            error: None,
+        }
+    }
+}
+
+/// A write, or the error it was turned down with, which is then reported when the query is run.
+impl IntoTerm for Result<Term, Error> {
+    fn into_term(self) -> Term {
+        match self {
+            Ok(term) => term,
+            Err(error) => Term {
+                tree: Tree::Datum(Value::Null),
+                error: Some(match error {
+                    Error::ReqlDriverCompileError(message) => message,
+                    error => format!("{}", error),
+                }),
+            },
+        }
+    }
+}
+
+impl IntoTerm for Value {
+    fn into_term(self) -> Term {
+        Tree::Datum(self).into_term()
+    }
+}
+
+impl<'a> IntoTerm for &'a str {
+    fn into_term(self) -> Term {
+        Value::from(self).into_term()
+    }
+}
+
+impl IntoTerm for String {
+    fn into_term(self) -> Term {
+        Value::from(self).into_term()
+    }
+}
+
+impl IntoTerm for bool {
+    fn into_term(self) -> Term {
+        Value::from(self).into_term()
+    }
+}
+
+impl IntoTerm for i32 {
+    fn into_term(self) -> Term {
+        Value::from(self).into_term()
+    }
+}
+
+impl IntoTerm for i64 {
+    fn into_term(self) -> Term {
+        Value::from(self).into_term()
+    }
+}
+
+impl IntoTerm for u32 {
+    fn into_term(self) -> Term {
+        Value::from(self).into_term()
+    }
+}
+
+impl IntoTerm for u64 {
+    fn into_term(self) -> Term {
+        Value::from(self).into_term()
+    }
+}
+
+impl IntoTerm for f64 {
+    fn into_term(self) -> Term {
+        Value::from(self).into_term()
+    }
+}
+
+impl IntoTerm for Time {
+    fn into_term(self) -> Term {
+        datum(self)
+    }
+}
+
+impl IntoTerm for Field {
+    fn into_term(self) -> Term {
+        datum(self)
+    }
+}
+
+impl IntoTerm for Binary {
+    fn into_term(self) -> Term {
+        datum(self)
+    }
+}
+
+impl IntoTerm for Geometry {
+    fn into_term(self) -> Term {
+        datum(self)
+    }
+}
+
+/// Objects of plain values stay plain values.  As soon as one of the values is a query, the object
+/// is built with MAKE_OBJ instead, so the server evaluates its fields.
+impl<T: IntoTerm> IntoTerm for BTreeMap<String, T> {
+    fn into_term(self) -> Term {
+        let mut error = None;
+        let mut fields = BTreeMap::new();
+        for (key, value) in self {
+            let value = value.into_term();
+            if error.is_none() {
+                error = value.error;
+            }
+            fields.insert(key, value.tree);
+        }
+
+        let datums = fields.values().all(|tree| match tree {
+            &Tree::Datum(_) => true,
+            _ => false,
+        });
+        let tree = if datums {
+            Tree::Datum(Value::Object(fields.into_iter().filter_map(|(key, tree)| match tree {
+                Tree::Datum(json) => Some((key, json)),
+                _ => None,
+            }).collect()))
+        } else {
+            Tree::Query {
+                head: Term_TermType::MAKE_OBJ,
+                tail: vec![],
+                optargs: fields,
+            }
+        };
+
+        Term {
+            tree: tree,
+            error: error,
+        }
+    }
+}
+
+/// Arrays of terms are built with MAKE_ARRAY, so their elements can be queries themselves.
+impl<T: IntoTerm> IntoTerm for Vec<T> {
+    fn into_term(self) -> Term {
+        Term::call(Term_TermType::MAKE_ARRAY, self.into_iter().map(IntoTerm::into_term).collect())
+    }
+}
+
+impl IntoTree for Term {
+    fn into_tree(self) -> Result<Tree, Error> {
+        match self.error {
+            Some(error) => Err(Error::ReqlDriverCompileError(error)),
+            None => Ok(self.tree),
+        }
+    }
+}
+
+/// Turns anything serde can serialize into a datum.  A value that fails to serialize (e.g. a map
+/// with non-string keys) is reported when the query is run, like any other bad call.
+fn datum<T: Serialize>(value: T) -> Term {
+    match serde_json::to_value(value) {
+        Ok(json) => json.into_term(),
+        Err(error) => Term {
+            tree: Tree::Datum(Value::Null),
+            error: Some(format!("Could not serialize a document: {}", error)),
+        },
+    }
+}
+
+/// The least and the most arguments a term takes, counting the term it is chained on.  `None`
+/// means there's no upper bound.
+fn arity(head: Term_TermType) -> (usize, Option<usize>) {
+    use super::super::ql2::Term_TermType::*;
+
+    match head {
+        IMPLICIT_VAR | DB_LIST | NOW | MINVAL | MAXVAL |
+        MONDAY | TUESDAY | WEDNESDAY | THURSDAY | FRIDAY | SATURDAY | SUNDAY |
+        JANUARY | FEBRUARY | MARCH | APRIL | MAY | JUNE | JULY | AUGUST | SEPTEMBER | OCTOBER |
+        NOVEMBER | DECEMBER |
+        MAKE_OBJ => (0, Some(0)),
+        UUID | ERROR | TABLE_LIST | WAIT | LITERAL => (0, Some(1)),
+        RANGE | RANDOM => (0, Some(2)),
+        MAKE_ARRAY | OBJECT | UNION | OR | AND => (0, None),
+        DATUM | VAR | JAVASCRIPT | HTTP | DB | NOT | FLOOR | CEIL | ROUND | KEYS | VALUES |
+        DISTINCT | IS_EMPTY | ZIP | TYPE_OF | DELETE | DB_CREATE | DB_DROP | CONFIG | STATUS |
+        RECONFIGURE | REBALANCE | SYNC | INDEX_LIST | GET_WRITE_HOOK | ASC | DESC | INFO |
+        UPCASE | DOWNCASE | JSON | TO_JSON_STRING | ISO8601 | TO_ISO8601 | EPOCH_TIME |
+        TO_EPOCH_TIME | DATE | TIME_OF_DAY | TIMEZONE | YEAR | MONTH | DAY | DAY_OF_WEEK |
+        DAY_OF_YEAR | HOURS | MINUTES | SECONDS | UNGROUP | CHANGES | ARGS | BINARY | GEOJSON |
+        TO_GEOJSON | FILL => (1, Some(1)),
+        TABLE | COUNT | TABLE_CREATE | TABLE_DROP | SUM | AVG | MIN | MAX => (1, Some(2)),
+        SPLIT => (1, Some(3)),
+        ADD | SUB | MUL | DIV | CONTAINS | HAS_FIELDS | WITH_FIELDS | PLUCK | WITHOUT | MERGE |
+        ORDER_BY | INDEX_STATUS | INDEX_WAIT | FUNCALL | GROUP => (1, None),
+        GET | MOD | APPEND | PREPEND | DIFFERENCE | SET_INSERT | SET_INTERSECTION | SET_UNION |
+        SET_DIFFERENCE | SKIP | LIMIT | OFFSETS_OF | GET_FIELD | REDUCE | FILTER | CONCAT_MAP |
+        NTH | BRACKET | COERCE_TO | UPDATE | REPLACE | INSERT | INDEX_DROP | SET_WRITE_HOOK |
+        FOR_EACH | FUNC | MATCH | SAMPLE | DEFAULT | IN_TIMEZONE | POINT | DISTANCE |
+        INTERSECTS | INCLUDES | CIRCLE | GET_INTERSECTING | GET_NEAREST | POLYGON_SUB => (2, Some(2)),
+        SLICE | DELETE_AT | GRANT | INDEX_CREATE => (2, Some(3)),
+        GET_ALL | EQ | NE | LT | LE | GT | GE | MAP | LINE => (2, None),
+        BETWEEN_DEPRECATED | BETWEEN | FOLD | INNER_JOIN | OUTER_JOIN | EQ_JOIN | INSERT_AT |
+        CHANGE_AT | SPLICE_AT | INDEX_RENAME | DURING => (3, Some(3)),
+        BRANCH | POLYGON => (3, None),
+        TIME => (4, Some(7)),
+    }
+}
+
+/// Writes can only be chained where the server allows them: `insert` on a table, the other writes
+/// on a table or a selection of one.
+///
+/// This is checked on the tree rather than with a type per kind of selection: behind `r.args`,
+/// `branch` or a function's argument, what a term selects is only known once the query runs, and
+/// every chainable method would need a copy for each type.  Those cases are let through.
+fn check_write_target(head: Term_TermType, target: &Tree) -> Option<String> {
+    let found = match head {
+        Term_TermType::INSERT |
+        Term_TermType::UPDATE |
+        Term_TermType::REPLACE |
+        Term_TermType::DELETE => target.selection_type(),
+        _ => return None,
+    };
+
+    match (head, found) {
+        (_, SelectionType::Unknown) |
+        (Term_TermType::INSERT, SelectionType::Table) => None,
+        (Term_TermType::INSERT, _) => Some("INSERT can only be chained on a table.".to_owned()),
+        (_, SelectionType::Other) => Some(format!("{:?} can only be chained on a table or a selection of one.", head)),
+        _ => None,
+    }
+}
+
+impl Term {
+    /// Builds a term of any type from its arguments, checking how many there are.  The chainable
+    /// methods below are all shorthands for this.
+    pub fn call(head: Term_TermType, args: Vec<Term>) -> Term {
+        let mut error = None;
+        let mut tail = Vec::with_capacity(args.len());
+        // ARGS splices its array into the arguments on the server, so there's no telling how many
+        // arguments there really are.
+        let mut spliced = false;
+        for arg in args {
+            if error.is_none() {
+                error = arg.error;
+            }
+            if let Tree::Query { head: Term_TermType::ARGS, .. } = arg.tree {
+                spliced = true;
+            }
+            tail.push(arg.tree);
+        }
+
+        if error.is_none() && !spliced {
+            let (min, max) = arity(head);
+            let n = tail.len();
+            if n < min || max.map_or(false, |max| n > max) {
+                let expected = match max {
+                    Some(max) if max == min => format!("{}", min),
+                    Some(max) => format!("between {} and {}", min, max),
+                    None => format!("at least {}", min),
+                };
+                error = Some(format!("{:?} expects {} argument(s), but got {}.", head, expected, n));
+            }
+        }
+
+        if error.is_none() {
+            error = tail.first().and_then(|target| check_write_target(head, target));
+        }
+
+        Term {
+            tree: Tree::query(head, tail),
+            error: error,
+        }
+    }
+
+    /// Sets a named optional argument on the outermost term, e.g. `shards` on a `table_create`.
+    /// The typed setters below cover the optargs the server knows about.
+    pub fn optarg<T: IntoTerm>(mut self, name: &str, value: T) -> Term {
+        let value = value.into_term();
+        if self.error.is_none() {
+            self.error = value.error;
+        }
+
+        match self.tree {
+            Tree::Query { ref mut optargs, .. } => {
+                optargs.insert(name.to_owned(), value.tree);
+            },
+            Tree::Datum(_) => if self.error.is_none() {
+                self.error = Some(format!("The `{}` optarg can only be set on a term, not on a plain value.", name));
+            },
+        }
+
+        self
+    }
+
+    pub(crate) fn as_tree(&self) -> &Tree {
+        &self.tree
+    }
+
+    /// Wraps this term in a new one, as its first argument.
+    fn chain(self, head: Term_TermType, args: Vec<Term>) -> Term {
+        let mut all = Vec::with_capacity(args.len() + 1);
+        all.push(self);
+        all.extend(args);
+
+        Term::call(head, all)
+    }
+
+    /// Wraps this term in a new one, followed by every element of `args`.
+    fn chain_all<T: IntoTerm>(self, head: Term_TermType, args: Vec<T>) -> Term {
+        self.chain(head, args.into_iter().map(IntoTerm::into_term).collect())
+    }
+
+    // Optargs
+
+    /// `table_create`: how many shards to split the table into.
+    pub fn shards(self, shards: u32) -> Term {
+        self.optarg("shards", shards)
+    }
+
+    /// `table_create` and `reconfigure`: the number of replicas, or an object of replicas per
+    /// server tag.
+    pub fn replicas<T: IntoTerm>(self, replicas: T) -> Term {
+        self.optarg("replicas", replicas)
+    }
+
+    /// `table_create`: the field holding each document's key.  Defaults to `id`.
+    pub fn primary_key(self, primary_key: &str) -> Term {
+        self.optarg("primary_key", primary_key)
+    }
+
+    /// `reconfigure`: the server tag the primary replicas are picked from.
+    pub fn primary_replica_tag(self, tag: &str) -> Term {
+        self.optarg("primary_replica_tag", tag)
+    }
+
+    /// `reconfigure`: shows the new configuration without applying it.
+    pub fn dry_run(self, dry_run: bool) -> Term {
+        self.optarg("dry_run", dry_run)
+    }
+
+    /// `table`: `single`, `majority` or `outdated`.
+    pub fn read_mode(self, read_mode: &str) -> Term {
+        self.optarg("read_mode", read_mode)
+    }
+
+    /// `table` and `config`: `name` or `uuid`.
+    pub fn identifier_format(self, format: &str) -> Term {
+        self.optarg("identifier_format", format)
+    }
+
+    /// Writes and `table_create`: `hard` or `soft`.
+    pub fn durability(self, durability: &str) -> Term {
+        self.optarg("durability", durability)
+    }
+
+    /// Writes: whether the result includes the old and new values of every changed document, or
+    /// `"always"` to include them even for unchanged ones.
+    pub fn return_changes<T: IntoTerm>(self, return_changes: T) -> Term {
+        self.optarg("return_changes", return_changes)
+    }
+
+    /// `insert`: `error`, `replace` or `update`.
+    pub fn conflict(self, conflict: &str) -> Term {
+        self.optarg("conflict", conflict)
+    }
+
+    /// `insert`: resolves conflicts with `function`, called with the key, the old document and the
+    /// new one.
+    pub fn conflict_with<F: FnOnce(Term, Term, Term) -> Term>(self, function: F) -> Term {
+        self.optarg("conflict", func3(function))
+    }
+
+    /// `insert`, `update`, `replace` and `delete`: skips the table's write hook.
+    pub fn ignore_write_hook(self, ignore: bool) -> Term {
+        self.optarg("ignore_write_hook", ignore)
+    }
+
+    /// `update` and `replace`: allows changes that can't be applied atomically, like ones calling
+    /// `r.js`.
+    pub fn non_atomic(self, non_atomic: bool) -> Term {
+        self.optarg("non_atomic", non_atomic)
+    }
+
+    /// `get_all`, `between`, `eq_join`, `order_by`, `group`, `distinct`, `min`, `max`,
+    /// `get_intersecting` and `get_nearest`: the secondary index to use.
+    pub fn index(self, index: &str) -> Term {
+        self.optarg("index", index)
+    }
+
+    /// `between` and `during`: `closed` or `open`.
+    pub fn left_bound(self, bound: &str) -> Term {
+        self.optarg("left_bound", bound)
+    }
+
+    /// `between` and `during`: `closed` or `open`.
+    pub fn right_bound(self, bound: &str) -> Term {
+        self.optarg("right_bound", bound)
+    }
+
+    /// `index_create`: whether the index holds one entry per element of the indexed array.  `group`:
+    /// whether elements go in a group per element of an array key.
+    pub fn multi(self, multi: bool) -> Term {
+        self.optarg("multi", multi)
+    }
+
+    /// `index_create`: whether the index is on geometry.
+    pub fn geo(self, geo: bool) -> Term {
+        self.optarg("geo", geo)
+    }
+
+    /// `index_rename`: whether to replace an index already going by the new name.
+    pub fn overwrite(self, overwrite: bool) -> Term {
+        self.optarg("overwrite", overwrite)
+    }
+
+    /// `eq_join` and `fold`: whether the order of the input is kept.
+    pub fn ordered(self, ordered: bool) -> Term {
+        self.optarg("ordered", ordered)
+    }
+
+    /// `union`: whether to merge ordered streams, or the field or function to merge them on.
+    pub fn interleave<T: IntoTerm>(self, interleave: T) -> Term {
+        self.optarg("interleave", interleave)
+    }
+
+    /// `filter`: what a missing field evaluates to.
+    pub fn default_value<T: IntoTerm>(self, value: T) -> Term {
+        self.optarg("default", value)
+    }
+
+    /// `changes`: `true` to squash changes over a second, or a number of seconds.
+    pub fn squash<T: IntoTerm>(self, squash: T) -> Term {
+        self.optarg("squash", squash)
+    }
+
+    /// `changes`: how many changes the server buffers before dropping them.
+    pub fn changefeed_queue_size(self, size: u64) -> Term {
+        self.optarg("changefeed_queue_size", size)
+    }
+
+    /// `changes`: whether the feed starts with the current results.
+    pub fn include_initial(self, include_initial: bool) -> Term {
+        self.optarg("include_initial", include_initial)
+    }
+
+    /// `changes`: whether the feed includes its state changes.
+    pub fn include_states(self, include_states: bool) -> Term {
+        self.optarg("include_states", include_states)
+    }
+
+    /// `changes`: whether each change says what kind of change it is.
+    pub fn include_types(self, include_types: bool) -> Term {
+        self.optarg("include_types", include_types)
+    }
+
+    /// `changes`: whether changes can reorder results from `order_by.limit`.
+    pub fn include_offsets(self, include_offsets: bool) -> Term {
+        self.optarg("include_offsets", include_offsets)
+    }
+
+    /// `iso8601`, `epoch_time` and `time`: the timezone of times without one.
+    pub fn default_timezone(self, timezone: &str) -> Term {
+        self.optarg("default_timezone", timezone)
+    }
+
+    /// `get_nearest`: the most results to return.
+    pub fn max_results(self, max_results: u64) -> Term {
+        self.optarg("max_results", max_results)
+    }
+
+    /// `get_nearest`: the farthest a result can be.
+    pub fn max_dist(self, max_dist: f64) -> Term {
+        self.optarg("max_dist", max_dist)
+    }
+
+    /// Geospatial terms: `m`, `km`, `mi`, `nm` or `ft`.
+    pub fn unit(self, unit: &str) -> Term {
+        self.optarg("unit", unit)
+    }
+
+    /// `circle`: how many vertices approximate the circle.
+    pub fn num_vertices(self, num_vertices: u32) -> Term {
+        self.optarg("num_vertices", num_vertices)
+    }
+
+    /// `circle`: whether to return a polygon rather than a line.
+    pub fn fill_circle(self, fill: bool) -> Term {
+        self.optarg("fill", fill)
+    }
+
+    /// Geospatial terms: `WGS84` or `unit_sphere`.
+    pub fn geo_system(self, geo_system: &str) -> Term {
+        self.optarg("geo_system", geo_system)
+    }
+
+    /// `wait`: `ready_for_outdated_reads`, `ready_for_reads`, `ready_for_writes` or
+    /// `all_replicas_ready`.
+    pub fn wait_for(self, wait_for: &str) -> Term {
+        self.optarg("wait_for", wait_for)
+    }
+
+    /// `wait`, `r.js` and `r.http`: how many seconds to wait.
+    pub fn timeout(self, seconds: f64) -> Term {
+        self.optarg("timeout", seconds)
+    }
+
+    // Databases and tables
+
+    pub fn table<T: IntoTerm>(self, name: T) -> Term {
+        self.chain(Term_TermType::TABLE, vec![name.into_term()])
+    }
+
+    pub fn table_create<T: IntoTerm>(self, name: T) -> Term {
+        self.chain(Term_TermType::TABLE_CREATE, vec![name.into_term()])
+    }
+
+    pub fn table_drop<T: IntoTerm>(self, name: T) -> Term {
+        self.chain(Term_TermType::TABLE_DROP, vec![name.into_term()])
+    }
+
+    pub fn table_list(self) -> Term {
+        self.chain(Term_TermType::TABLE_LIST, vec![])
+    }
+
+    pub fn config(self) -> Term {
+        self.chain(Term_TermType::CONFIG, vec![])
+    }
+
+    pub fn status(self) -> Term {
+        self.chain(Term_TermType::STATUS, vec![])
+    }
+
+    pub fn wait(self) -> Term {
+        self.chain(Term_TermType::WAIT, vec![])
+    }
+
+    pub fn reconfigure(self) -> Term {
+        self.chain(Term_TermType::RECONFIGURE, vec![])
+    }
+
+    pub fn rebalance(self) -> Term {
+        self.chain(Term_TermType::REBALANCE, vec![])
+    }
+
+    pub fn sync(self) -> Term {
+        self.chain(Term_TermType::SYNC, vec![])
+    }
+
+    pub fn grant<U: IntoTerm, P: IntoTerm>(self, user: U, permissions: P) -> Term {
+        self.chain(Term_TermType::GRANT, vec![user.into_term(), permissions.into_term()])
+    }
+
+    // Indexes
+
+    pub fn index_create<T: IntoTerm>(self, name: T) -> Term {
+        self.chain(Term_TermType::INDEX_CREATE, vec![name.into_term()])
+    }
+
+    /// Creates an index on whatever `function` returns for each document, e.g. a compound index
+    /// with `|doc| r.expr(vec![doc.get_field("last"), doc.get_field("first")])`.
+    pub fn index_create_with<T: IntoTerm, F: FnOnce(Term) -> Term>(self, name: T, function: F) -> Term {
+        self.chain(Term_TermType::INDEX_CREATE, vec![name.into_term(), func1(function)])
+    }
+
+    /// Creates an index from the `function` of another index's status, to copy it to another table
+    /// or recreate it after a migration.
+    pub fn index_create_from<T: IntoTerm>(self, name: T, function: Binary) -> Term {
+        self.chain(Term_TermType::INDEX_CREATE, vec![name.into_term(), function.into_term()])
+    }
+
+    pub fn index_drop<T: IntoTerm>(self, name: T) -> Term {
+        self.chain(Term_TermType::INDEX_DROP, vec![name.into_term()])
+    }
+
+    pub fn index_list(self) -> Term {
+        self.chain(Term_TermType::INDEX_LIST, vec![])
+    }
+
+    pub fn index_rename<T: IntoTerm, U: IntoTerm>(self, old_name: T, new_name: U) -> Term {
+        self.chain(Term_TermType::INDEX_RENAME, vec![old_name.into_term(), new_name.into_term()])
+    }
+
+    /// The status of the named indexes, or of every index if `names` is empty.
+    pub fn index_status<T: IntoTerm>(self, names: Vec<T>) -> Term {
+        self.chain_all(Term_TermType::INDEX_STATUS, names)
+    }
+
+    /// Waits for the named indexes to be ready, or for every index if `names` is empty.
+    pub fn index_wait<T: IntoTerm>(self, names: Vec<T>) -> Term {
+        self.chain_all(Term_TermType::INDEX_WAIT, names)
+    }
+
+    /// Sets the function, called with the context, the old document and the new one, that every
+    /// write to the table goes through.  A null term removes it.
+    pub fn set_write_hook<A, F: Lambda<A>>(self, function: F) -> Term {
+        self.chain(Term_TermType::SET_WRITE_HOOK, vec![function.into_func()])
+    }
+
+    pub fn get_write_hook(self) -> Term {
+        self.chain(Term_TermType::GET_WRITE_HOOK, vec![])
+    }
+
+    // Writes
+    //
+    // These fail with `Error::ReqlDriverCompileError` right away when chained on a value that isn't
+    // a table, or a selection of one for the writes other than `insert`.
+
+    /// Wraps this term in a write, once it is known to be something the write can go to.
+    fn write(self, head: Term_TermType, args: Vec<Term>) -> Result<Term, Error> {
+        if self.error.is_none() {
+            if let Some(error) = check_write_target(head, &self.tree) {
+                return Err(Error::ReqlDriverCompileError(error));
+            }
+        }
+
+        Ok(self.chain(head, args))
+    }
+
+    /// Inserts a document, or an array of them, as serialized by serde.
+    pub fn insert<T: Serialize>(self, documents: T) -> Result<Term, Error> {
+        self.write(Term_TermType::INSERT, vec![datum(documents)])
+    }
+
+    /// Inserts the documents a query returns, e.g. another table.
+    pub fn insert_with<T: IntoTerm>(self, documents: T) -> Result<Term, Error> {
+        self.write(Term_TermType::INSERT, vec![documents.into_term()])
+    }
+
+    /// Merges the fields of `changes`, as serialized by serde, into each document.
+    pub fn update<T: Serialize>(self, changes: T) -> Result<Term, Error> {
+        self.write(Term_TermType::UPDATE, vec![datum(changes)])
+    }
+
+    /// Like `update`, with changes built in ReQL: a term using `r.row()`, or a closure like
+    /// `|doc: Term| ...`.
+    pub fn update_with<T: IntoTerm>(self, changes: T) -> Result<Term, Error> {
+        self.write(Term_TermType::UPDATE, vec![func_wrap(changes)])
+    }
+
+    /// Replaces each document with `document`, as serialized by serde.
+    pub fn replace<T: Serialize>(self, document: T) -> Result<Term, Error> {
+        self.write(Term_TermType::REPLACE, vec![datum(document)])
+    }
+
+    /// Like `replace`, with the new document built in ReQL.
+    pub fn replace_with<T: IntoTerm>(self, document: T) -> Result<Term, Error> {
+        self.write(Term_TermType::REPLACE, vec![func_wrap(document)])
+    }
+
+    pub fn delete(self) -> Result<Term, Error> {
+        self.write(Term_TermType::DELETE, vec![])
+    }
+
+    // Selections
+
+    /// The document of a table with the primary key `key`, or `null`.
+    pub fn get<T: IntoTerm>(self, key: T) -> Term {
+        self.chain(Term_TermType::GET, vec![key.into_term()])
+    }
+
+    /// The documents with any of `keys` as their primary key, or as the value of the secondary
+    /// index set with `index`.
+    pub fn get_all<T: IntoTerm>(self, keys: Vec<T>) -> Term {
+        self.chain_all(Term_TermType::GET_ALL, keys)
+    }
+
+    /// The documents with keys from `lower`, included, up to `upper`, excluded, unless changed with
+    /// `left_bound` and `right_bound`.  Either side is left open with `r.minval()` or
+    /// `r.maxval()`.
+    pub fn between<T: IntoTerm, U: IntoTerm>(self, lower: T, upper: U) -> Term {
+        self.chain(Term_TermType::BETWEEN, vec![lower.into_term(), upper.into_term()])
+    }
+
+    /// Keeps the elements for which `predicate`, e.g. `|doc| doc.get_field("age").gt(18)`, holds.
+    pub fn filter<F: FnOnce(Term) -> Term>(self, predicate: F) -> Term {
+        self.chain(Term_TermType::FILTER, vec![func1(predicate)])
+    }
+
+    /// Like `filter`, with a predicate that isn't a closure: an object the elements have to match,
+    /// or a term using `r.row()`.
+    pub fn filter_by<T: IntoTerm>(self, predicate: T) -> Term {
+        self.chain(Term_TermType::FILTER, vec![func_wrap(predicate)])
+    }
+
+    // Joins
+
+    /// Pairs up the elements of both sequences for which `predicate`, e.g. `|left, right| ...`,
+    /// holds.
+    pub fn inner_join<T: IntoTerm, F: FnOnce(Term, Term) -> Term>(self, other: T, predicate: F) -> Term {
+        self.chain(Term_TermType::INNER_JOIN, vec![other.into_term(), func2(predicate)])
+    }
+
+    /// Like `inner_join`, keeping the elements of this sequence without a match on their own.
+    pub fn outer_join<T: IntoTerm, F: FnOnce(Term, Term) -> Term>(self, other: T, predicate: F) -> Term {
+        self.chain(Term_TermType::OUTER_JOIN, vec![other.into_term(), func2(predicate)])
+    }
+
+    /// Pairs up each element with the document of `table` whose primary key, or `index`, is equal
+    /// to `field`: a field name, or a function of the element.
+    pub fn eq_join<T: IntoTerm, U: IntoTerm>(self, field: T, table: U) -> Term {
+        self.chain(Term_TermType::EQ_JOIN, vec![func_wrap(field), table.into_term()])
+    }
+
+    /// Merges the sides of each pair a join returns into one document.
+    pub fn zip(self) -> Term {
+        self.chain(Term_TermType::ZIP, vec![])
+    }
+
+    // Transformations
+
+    pub fn map<F: FnOnce(Term) -> Term>(self, function: F) -> Term {
+        self.chain(Term_TermType::MAP, vec![func1(function)])
+    }
+
+    pub fn with_fields<T: IntoTerm>(self, fields: Vec<T>) -> Term {
+        self.chain_all(Term_TermType::WITH_FIELDS, fields)
+    }
+
+    pub fn concat_map<F: FnOnce(Term) -> Term>(self, function: F) -> Term {
+        self.chain(Term_TermType::CONCAT_MAP, vec![func1(function)])
+    }
+
+    /// Orders by each of `keys` in turn: field names, functions, or `r.asc(...)`/`r.desc(...)`.
+    /// The server only takes ASC and DESC as they are, so they wrap their own key.
+    pub fn order_by<T: IntoTerm>(self, keys: Vec<T>) -> Term {
+        let keys = keys.into_iter().map(|key| {
+            let key = key.into_term();
+            match key.as_tree() {
+                &Tree::Query { head: Term_TermType::ASC, .. } |
+                &Tree::Query { head: Term_TermType::DESC, .. } => key,
+                _ => func_wrap(key),
+            }
+        }).collect();
+
+        self.chain(Term_TermType::ORDER_BY, keys)
+    }
+
+    pub fn skip<T: IntoTerm>(self, n: T) -> Term {
+        self.chain(Term_TermType::SKIP, vec![n.into_term()])
+    }
+
+    pub fn limit<T: IntoTerm>(self, n: T) -> Term {
+        self.chain(Term_TermType::LIMIT, vec![n.into_term()])
+    }
+
+    pub fn slice<T: IntoTerm, U: IntoTerm>(self, start: T, end: U) -> Term {
+        self.chain(Term_TermType::SLICE, vec![start.into_term(), end.into_term()])
+    }
+
+    pub fn nth<T: IntoTerm>(self, index: T) -> Term {
+        self.chain(Term_TermType::NTH, vec![index.into_term()])
+    }
+
+    pub fn offsets_of<T: IntoTerm>(self, predicate: T) -> Term {
+        self.chain(Term_TermType::OFFSETS_OF, vec![func_wrap(predicate)])
+    }
+
+    pub fn is_empty(self) -> Term {
+        self.chain(Term_TermType::IS_EMPTY, vec![])
+    }
+
+    pub fn union<T: IntoTerm>(self, other: T) -> Term {
+        self.chain(Term_TermType::UNION, vec![other.into_term()])
+    }
+
+    pub fn sample<T: IntoTerm>(self, n: T) -> Term {
+        self.chain(Term_TermType::SAMPLE, vec![n.into_term()])
+    }
+
+    // Aggregation
+
+    /// Groups by each of `keys`: field names or functions.  What follows up to `ungroup` runs on
+    /// each group, and the result is decoded with `Grouped`.
+    pub fn group<T: IntoTerm>(self, keys: Vec<T>) -> Term {
+        self.chain(Term_TermType::GROUP, keys.into_iter().map(func_wrap).collect())
+    }
+
+    pub fn ungroup(self) -> Term {
+        self.chain(Term_TermType::UNGROUP, vec![])
+    }
+
+    /// Combines the elements two at a time with `function`, e.g. `|a, b| a.add(b)`.
+    pub fn reduce<F: FnOnce(Term, Term) -> Term>(self, function: F) -> Term {
+        self.chain(Term_TermType::REDUCE, vec![func2(function)])
+    }
+
+    /// Like `reduce`, starting from `base` and going through the elements in order.
+    pub fn fold<T: IntoTerm, F: FnOnce(Term, Term) -> Term>(self, base: T, function: F) -> Term {
+        self.chain(Term_TermType::FOLD, vec![base.into_term(), func2(function)])
+    }
+
+    pub fn count(self) -> Term {
+        self.chain(Term_TermType::COUNT, vec![])
+    }
+
+    /// Counts the elements equal to a value, or for which a function holds.
+    pub fn count_by<T: IntoTerm>(self, value: T) -> Term {
+        self.chain(Term_TermType::COUNT, vec![func_wrap(value)])
+    }
+
+    pub fn sum(self) -> Term {
+        self.chain(Term_TermType::SUM, vec![])
+    }
+
+    /// Sums a field, or what a function returns, over the elements.
+    pub fn sum_by<T: IntoTerm>(self, field: T) -> Term {
+        self.chain(Term_TermType::SUM, vec![func_wrap(field)])
+    }
+
+    pub fn avg(self) -> Term {
+        self.chain(Term_TermType::AVG, vec![])
+    }
+
+    /// Averages a field, or what a function returns, over the elements.
+    pub fn avg_by<T: IntoTerm>(self, field: T) -> Term {
+        self.chain(Term_TermType::AVG, vec![func_wrap(field)])
+    }
+
+    /// The lowest element, or on a table the first one in the secondary index set with `index`.
+    pub fn min(self) -> Term {
+        self.chain(Term_TermType::MIN, vec![])
+    }
+
+    /// The element with the lowest value of a field, or of what a function returns.
+    pub fn min_by<T: IntoTerm>(self, field: T) -> Term {
+        self.chain(Term_TermType::MIN, vec![func_wrap(field)])
+    }
+
+    /// The highest element, or on a table the last one in the secondary index set with `index`.
+    pub fn max(self) -> Term {
+        self.chain(Term_TermType::MAX, vec![])
+    }
+
+    /// The element with the highest value of a field, or of what a function returns.
+    pub fn max_by<T: IntoTerm>(self, field: T) -> Term {
+        self.chain(Term_TermType::MAX, vec![func_wrap(field)])
+    }
+
+    pub fn distinct(self) -> Term {
+        self.chain(Term_TermType::DISTINCT, vec![])
+    }
+
+    pub fn contains<T: IntoTerm>(self, values: Vec<T>) -> Term {
+        self.chain(Term_TermType::CONTAINS, values.into_iter().map(func_wrap).collect())
+    }
+
+    // Document manipulation
+
+    /// Keeps only `fields`: names, or `Field` for fields nested in objects and arrays.
+    pub fn pluck<T: IntoTerm>(self, fields: Vec<T>) -> Term {
+        self.chain_all(Term_TermType::PLUCK, fields)
+    }
+
+    /// Leaves `fields` out, picked the same way as with `pluck`.
+    pub fn without<T: IntoTerm>(self, fields: Vec<T>) -> Term {
+        self.chain_all(Term_TermType::WITHOUT, fields)
+    }
+
+    pub fn merge<T: IntoTerm>(self, other: T) -> Term {
+        self.chain(Term_TermType::MERGE, vec![func_wrap(other)])
+    }
+
+    pub fn append<T: IntoTerm>(self, value: T) -> Term {
+        self.chain(Term_TermType::APPEND, vec![value.into_term()])
+    }
+
+    pub fn prepend<T: IntoTerm>(self, value: T) -> Term {
+        self.chain(Term_TermType::PREPEND, vec![value.into_term()])
+    }
+
+    pub fn difference<T: IntoTerm>(self, other: T) -> Term {
+        self.chain(Term_TermType::DIFFERENCE, vec![other.into_term()])
+    }
+
+    pub fn set_insert<T: IntoTerm>(self, value: T) -> Term {
+        self.chain(Term_TermType::SET_INSERT, vec![value.into_term()])
+    }
+
+    pub fn set_union<T: IntoTerm>(self, other: T) -> Term {
+        self.chain(Term_TermType::SET_UNION, vec![other.into_term()])
+    }
+
+    pub fn set_intersection<T: IntoTerm>(self, other: T) -> Term {
+        self.chain(Term_TermType::SET_INTERSECTION, vec![other.into_term()])
+    }
+
+    pub fn set_difference<T: IntoTerm>(self, other: T) -> Term {
+        self.chain(Term_TermType::SET_DIFFERENCE, vec![other.into_term()])
+    }
+
+    /// `doc("field")` in the other drivers: a field of an object, or an element of an array.
+    pub fn bracket<T: IntoTerm>(self, field: T) -> Term {
+        self.chain(Term_TermType::BRACKET, vec![field.into_term()])
+    }
+
+    pub fn get_field<T: IntoTerm>(self, field: T) -> Term {
+        self.chain(Term_TermType::GET_FIELD, vec![field.into_term()])
+    }
+
+    pub fn has_fields<T: IntoTerm>(self, fields: Vec<T>) -> Term {
+        self.chain_all(Term_TermType::HAS_FIELDS, fields)
+    }
+
+    pub fn insert_at<T: IntoTerm, U: IntoTerm>(self, index: T, value: U) -> Term {
+        self.chain(Term_TermType::INSERT_AT, vec![index.into_term(), value.into_term()])
+    }
+
+    pub fn splice_at<T: IntoTerm, U: IntoTerm>(self, index: T, values: U) -> Term {
+        self.chain(Term_TermType::SPLICE_AT, vec![index.into_term(), values.into_term()])
+    }
+
+    pub fn delete_at<T: IntoTerm>(self, index: T) -> Term {
+        self.chain(Term_TermType::DELETE_AT, vec![index.into_term()])
+    }
+
+    /// Deletes the elements from `start` up to, but not including, `end`.
+    pub fn delete_range<T: IntoTerm, U: IntoTerm>(self, start: T, end: U) -> Term {
+        self.chain(Term_TermType::DELETE_AT, vec![start.into_term(), end.into_term()])
+    }
+
+    pub fn change_at<T: IntoTerm, U: IntoTerm>(self, index: T, value: U) -> Term {
+        self.chain(Term_TermType::CHANGE_AT, vec![index.into_term(), value.into_term()])
+    }
+
+    pub fn keys(self) -> Term {
+        self.chain(Term_TermType::KEYS, vec![])
+    }
+
+    pub fn values(self) -> Term {
+        self.chain(Term_TermType::VALUES, vec![])
+    }
+
+    // Strings
+
+    pub fn match_<T: IntoTerm>(self, regex: T) -> Term {
+        self.chain(Term_TermType::MATCH, vec![regex.into_term()])
+    }
+
+    /// Splits on whitespace.
+    pub fn split(self) -> Term {
+        self.chain(Term_TermType::SPLIT, vec![])
+    }
+
+    pub fn split_on<T: IntoTerm>(self, separator: T) -> Term {
+        self.chain(Term_TermType::SPLIT, vec![separator.into_term()])
+    }
+
+    pub fn upcase(self) -> Term {
+        self.chain(Term_TermType::UPCASE, vec![])
+    }
+
+    pub fn downcase(self) -> Term {
+        self.chain(Term_TermType::DOWNCASE, vec![])
+    }
+
+    // Math and logic
+
+    pub fn add<T: IntoTerm>(self, other: T) -> Term {
+        self.chain(Term_TermType::ADD, vec![other.into_term()])
+    }
+
+    pub fn sub<T: IntoTerm>(self, other: T) -> Term {
+        self.chain(Term_TermType::SUB, vec![other.into_term()])
+    }
+
+    pub fn mul<T: IntoTerm>(self, other: T) -> Term {
+        self.chain(Term_TermType::MUL, vec![other.into_term()])
+    }
+
+    pub fn div<T: IntoTerm>(self, other: T) -> Term {
+        self.chain(Term_TermType::DIV, vec![other.into_term()])
+    }
+
+    pub fn mod_<T: IntoTerm>(self, other: T) -> Term {
+        self.chain(Term_TermType::MOD, vec![other.into_term()])
+    }
+
+    pub fn and<T: IntoTerm>(self, other: T) -> Term {
+        self.chain(Term_TermType::AND, vec![other.into_term()])
+    }
+
+    pub fn or<T: IntoTerm>(self, other: T) -> Term {
+        self.chain(Term_TermType::OR, vec![other.into_term()])
+    }
+
+    pub fn eq<T: IntoTerm>(self, other: T) -> Term {
+        self.chain(Term_TermType::EQ, vec![other.into_term()])
+    }
+
+    pub fn ne<T: IntoTerm>(self, other: T) -> Term {
+        self.chain(Term_TermType::NE, vec![other.into_term()])
+    }
+
+    pub fn gt<T: IntoTerm>(self, other: T) -> Term {
+        self.chain(Term_TermType::GT, vec![other.into_term()])
+    }
+
+    pub fn ge<T: IntoTerm>(self, other: T) -> Term {
+        self.chain(Term_TermType::GE, vec![other.into_term()])
+    }
+
+    pub fn lt<T: IntoTerm>(self, other: T) -> Term {
+        self.chain(Term_TermType::LT, vec![other.into_term()])
+    }
+
+    pub fn le<T: IntoTerm>(self, other: T) -> Term {
+        self.chain(Term_TermType::LE, vec![other.into_term()])
+    }
+
+    pub fn not(self) -> Term {
+        self.chain(Term_TermType::NOT, vec![])
+    }
+
+    pub fn floor(self) -> Term {
+        self.chain(Term_TermType::FLOOR, vec![])
+    }
+
+    pub fn ceil(self) -> Term {
+        self.chain(Term_TermType::CEIL, vec![])
+    }
+
+    pub fn round(self) -> Term {
+        self.chain(Term_TermType::ROUND, vec![])
+    }
+
+    // Dates and times
+
+    pub fn in_timezone<T: IntoTerm>(self, timezone: T) -> Term {
+        self.chain(Term_TermType::IN_TIMEZONE, vec![timezone.into_term()])
+    }
+
+    pub fn timezone(self) -> Term {
+        self.chain(Term_TermType::TIMEZONE, vec![])
+    }
+
+    pub fn during<T: IntoTerm, U: IntoTerm>(self, start: T, end: U) -> Term {
+        self.chain(Term_TermType::DURING, vec![start.into_term(), end.into_term()])
+    }
+
+    pub fn date(self) -> Term {
+        self.chain(Term_TermType::DATE, vec![])
+    }
+
+    pub fn time_of_day(self) -> Term {
+        self.chain(Term_TermType::TIME_OF_DAY, vec![])
+    }
+
+    pub fn year(self) -> Term {
+        self.chain(Term_TermType::YEAR, vec![])
+    }
+
+    pub fn month(self) -> Term {
+        self.chain(Term_TermType::MONTH, vec![])
+    }
+
+    pub fn day(self) -> Term {
+        self.chain(Term_TermType::DAY, vec![])
+    }
+
+    pub fn day_of_week(self) -> Term {
+        self.chain(Term_TermType::DAY_OF_WEEK, vec![])
+    }
+
+    pub fn day_of_year(self) -> Term {
+        self.chain(Term_TermType::DAY_OF_YEAR, vec![])
+    }
+
+    pub fn hours(self) -> Term {
+        self.chain(Term_TermType::HOURS, vec![])
+    }
+
+    pub fn minutes(self) -> Term {
+        self.chain(Term_TermType::MINUTES, vec![])
+    }
+
+    pub fn seconds(self) -> Term {
+        self.chain(Term_TermType::SECONDS, vec![])
+    }
+
+    pub fn to_iso8601(self) -> Term {
+        self.chain(Term_TermType::TO_ISO8601, vec![])
+    }
+
+    pub fn to_epoch_time(self) -> Term {
+        self.chain(Term_TermType::TO_EPOCH_TIME, vec![])
+    }
+
+    // Control structures
+
+    /// Calls `function` with this term as its argument.
+    pub fn do_<F: FnOnce(Term) -> Term>(self, function: F) -> Term {
+        Term::call(Term_TermType::FUNCALL, vec![func1(function), self])
+    }
+
+    /// Runs the writes `function` returns for each element, e.g.
+    /// `|user: Term| r.table("archive").get(user.get_field("id")).delete()`.
+    pub fn for_each<T: IntoTerm, F: FnOnce(Term) -> T>(self, function: F) -> Term {
+        self.chain(Term_TermType::FOR_EACH, vec![func1(|var| function(var).into_term())])
+    }
+
+    /// The value to use instead when this term is null or fails with a non-existence error.
+    pub fn default<T: IntoTerm>(self, value: T) -> Term {
+        self.chain(Term_TermType::DEFAULT, vec![value.into_term()])
+    }
+
+    pub fn coerce_to<T: IntoTerm>(self, type_name: T) -> Term {
+        self.chain(Term_TermType::COERCE_TO, vec![type_name.into_term()])
+    }
+
+    pub fn type_of(self) -> Term {
+        self.chain(Term_TermType::TYPE_OF, vec![])
+    }
+
+    pub fn info(self) -> Term {
+        self.chain(Term_TermType::INFO, vec![])
+    }
+
+    pub fn to_json_string(self) -> Term {
+        self.chain(Term_TermType::TO_JSON_STRING, vec![])
+    }
+
+    /// Turns a table, a selection or a single document into a changefeed, to run with
+    /// `run_cursor` and decode into `results::Change`.  It only ends when it is dropped, or when the
+    /// connection is lost.
+    pub fn changes(self) -> Term {
+        self.chain(Term_TermType::CHANGES, vec![])
+    }
+
+    // Geospatial
+
+    pub fn distance<T: IntoTerm>(self, other: T) -> Term {
+        self.chain(Term_TermType::DISTANCE, vec![other.into_term()])
+    }
+
+    pub fn fill(self) -> Term {
+        self.chain(Term_TermType::FILL, vec![])
+    }
+
+    pub fn to_geojson(self) -> Term {
+        self.chain(Term_TermType::TO_GEOJSON, vec![])
+    }
+
+    pub fn get_intersecting<T: IntoTerm>(self, geometry: T) -> Term {
+        self.chain(Term_TermType::GET_INTERSECTING, vec![geometry.into_term()])
+    }
+
+    pub fn get_nearest<T: IntoTerm>(self, point: T) -> Term {
+        self.chain(Term_TermType::GET_NEAREST, vec![point.into_term()])
+    }
+
+    pub fn includes<T: IntoTerm>(self, geometry: T) -> Term {
+        self.chain(Term_TermType::INCLUDES, vec![geometry.into_term()])
+    }
+
+    pub fn intersects<T: IntoTerm>(self, geometry: T) -> Term {
+        self.chain(Term_TermType::INTERSECTS, vec![geometry.into_term()])
+    }
+
+    pub fn polygon_sub<T: IntoTerm>(self, other: T) -> Term {
+        self.chain(Term_TermType::POLYGON_SUB, vec![other.into_term()])
+    }
+}
@@ -1,44 +1,215 @@
+use error::Error;
 use protobuf::ProtobufEnum;
-use rustc_serialize::json::{Json, ToJson};
+use serde::ser::{Serialize, SerializeSeq, Serializer};
+use serde_json::Value;
+use std::collections::BTreeMap;
 use super::super::ql2::Term_TermType;
+use super::types;
 
 /// Each ReQL query/command is represented as a tree, which is serialized to a JSON array like:
 ///
+/// ```text
+/// [<term>, [<tree0>, <tree1>, ...], {<name>: <tree>, ...}]
 /// ```
-/// [<term>, <tree0>, <tree1>, ...]
-/// ```
+///
+/// The object holds the named optional arguments (optargs) of the term, and is left out when there
+/// are none.  The argument array is left out too for terms without any arguments.
 ///
 /// An array is a new query, unless it is prefixed with the MAKE_ARRAY term, in which case what
-/// follows is a plain JSON array.  Plain JSON elements are represented with the Tree::Datum type.
+/// follows is a plain JSON array.  Plain JSON elements are represented with the Tree::Datum type,
+/// whose arrays are wrapped in MAKE_ARRAY at every depth when they are serialized.
+#[derive(Clone, Debug, PartialEq)]
 pub enum Tree {
     Query {
         head: Term_TermType,
         tail: Vec<Tree>,
+        optargs: BTreeMap<String, Tree>,
     },
-    Datum(Json)
+    Datum(Value)
+}
+
+/// What a tree evaluates to, as far as writes are concerned: `insert` needs a table, and `update`,
+/// `replace` and `delete` need a table or a selection of its documents.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SelectionType {
+    Table,
+    /// Several documents of a table, like `get_all` and `between` return.
+    Selection,
+    /// One document of a table, like `get` returns.
+    SingleSelection,
+    /// A value of any other type, which can't be written to.
+    Other,
+    /// Can't be told before the query runs, like a function's argument.
+    Unknown,
+}
+
+/// Anything that can be run as a query: a finished tree, or a term that still has to be checked.
+pub trait IntoTree {
+    fn into_tree(self) -> Result<Tree, Error>;
 }
 
-impl ToJson for Tree {
-    fn to_json(&self) -> Json {
+impl IntoTree for Tree {
+    fn into_tree(self) -> Result<Tree, Error> {
+        Ok(self)
+    }
+}
+
+impl Serialize for Tree {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         match self {
             &Tree::Query {
                 head,
                 ref tail,
+                ref optargs,
             } => {
-                // Return a JSON array, with the term first (as JSON).  Then, recursively convert
-                // any other parts of the query to JSON.
-                let mut array = vec![head.value().to_json()];
-                array.extend(tail.iter().map(|tree| tree.to_json()).collect::<Vec<Json>>());
+                // Write a JSON array, with the term first.  Then the arguments, recursively, in an
+                // array of their own, and the optargs in an object.
+                let has_args = !tail.is_empty() || !optargs.is_empty();
+                let len = 1 + has_args as usize + !optargs.is_empty() as usize;
+                let mut array = serializer.serialize_seq(Some(len))?;
+                array.serialize_element(&head.value())?;
+                if has_args {
+                    array.serialize_element(tail)?;
+                }
+                if !optargs.is_empty() {
+                    array.serialize_element(optargs)?;
+                }
+
+                array.end()
+            },
+            &Tree::Datum(ref json) => Datum(json).serialize(serializer),
+        }
+    }
+}
+
+/// A plain value, written so the server reads back the same value.  JSON arrays have to be
+/// transformed to the special RethinkDB representation, which uses the MAKE_ARRAY term, wherever
+/// they are: otherwise the server takes them for queries.  Values wrapped in `types::Literal` become
+/// LITERAL terms.
+struct Datum<'a>(&'a Value);
+
+/// The elements of an array datum, each written as a datum in turn.
+struct Elements<'a>(&'a [Value]);
+
+impl<'a> Serialize for Datum<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            &Value::Array(ref array) => (Term_TermType::MAKE_ARRAY.value(), Elements(array)).serialize(serializer),
+            &Value::Object(ref object) => match types::literal_value(object) {
+                Some(None) => (Term_TermType::LITERAL.value(),).serialize(serializer),
+                Some(Some(value)) => (Term_TermType::LITERAL.value(), [Datum(value)]).serialize(serializer),
+                None => serializer.collect_map(object.iter().map(|(key, value)| (key, Datum(value)))),
+            },
+            json => json.serialize(serializer),
+        }
+    }
+}
 
-                Json::Array(array)
+impl<'a> Serialize for Elements<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter().map(Datum))
+    }
+}
+
+impl Tree {
+    /// A term without optargs.
+    pub fn query(head: Term_TermType, tail: Vec<Tree>) -> Tree {
+        Tree::Query {
+            head: head,
+            tail: tail,
+            optargs: BTreeMap::new(),
+        }
+    }
+
+    /// Whether the tree uses `r.row()` outside of any function.
+    pub fn has_implicit_var(&self) -> bool {
+        match self {
+            &Tree::Query { head: Term_TermType::IMPLICIT_VAR, .. } => true,
+            // `r.row()` inside a function refers to that function's argument.
+            &Tree::Query { head: Term_TermType::FUNC, .. } => false,
+            &Tree::Query { ref tail, ref optargs, .. } => {
+                tail.iter().chain(optargs.values()).any(|tree| tree.has_implicit_var())
+            },
+            &Tree::Datum(_) => false,
+        }
+    }
+
+    /// Whether running the query can't change anything on the server, so it's safe to run it again
+    /// after the connection breaks.  Terms with side effects we can't see (JavaScript, HTTP) count
+    /// as writes.
+    pub fn is_read_only(&self) -> bool {
+        match self {
+            &Tree::Query {
+                head,
+                ref tail,
+                ref optargs,
+            } => match head {
+                Term_TermType::INSERT |
+                Term_TermType::UPDATE |
+                Term_TermType::REPLACE |
+                Term_TermType::DELETE |
+                Term_TermType::SYNC |
+                Term_TermType::DB_CREATE |
+                Term_TermType::DB_DROP |
+                Term_TermType::TABLE_CREATE |
+                Term_TermType::TABLE_DROP |
+                Term_TermType::INDEX_CREATE |
+                Term_TermType::INDEX_DROP |
+                Term_TermType::INDEX_RENAME |
+                Term_TermType::SET_WRITE_HOOK |
+                Term_TermType::RECONFIGURE |
+                Term_TermType::REBALANCE |
+                Term_TermType::GRANT |
+                Term_TermType::HTTP |
+                Term_TermType::JAVASCRIPT => false,
+                _ => tail.iter().chain(optargs.values()).all(|tree| tree.is_read_only()),
+            },
+            &Tree::Datum(_) => true,
+        }
+    }
+
+    /// Whether the tree evaluates to a table, to a selection of one, or to something else.
+    pub fn selection_type(&self) -> SelectionType {
+        let (head, first) = match self {
+            &Tree::Query { head, ref tail, .. } => (head, tail.first().map(Tree::selection_type)),
+            &Tree::Datum(_) => return SelectionType::Other,
+        };
+
+        match (head, first) {
+            (Term_TermType::TABLE, _) => SelectionType::Table,
+            (Term_TermType::GET, _) => SelectionType::SingleSelection,
+            (Term_TermType::GET_ALL, _) |
+            (Term_TermType::BETWEEN, _) |
+            (Term_TermType::BETWEEN_DEPRECATED, _) |
+            (Term_TermType::GET_INTERSECTING, _) => SelectionType::Selection,
+            // Terms narrowing down a selection keep it one.
+            (Term_TermType::FILTER, Some(first)) |
+            (Term_TermType::ORDER_BY, Some(first)) |
+            (Term_TermType::SKIP, Some(first)) |
+            (Term_TermType::LIMIT, Some(first)) |
+            (Term_TermType::SLICE, Some(first)) |
+            (Term_TermType::SAMPLE, Some(first)) => match first {
+                SelectionType::Table | SelectionType::Selection => SelectionType::Selection,
+                SelectionType::Unknown => SelectionType::Unknown,
+                _ => SelectionType::Other,
+            },
+            (Term_TermType::NTH, Some(first)) |
+            (Term_TermType::MIN, Some(first)) |
+            (Term_TermType::MAX, Some(first)) => match first {
+                SelectionType::Table | SelectionType::Selection => SelectionType::SingleSelection,
+                SelectionType::Unknown => SelectionType::Unknown,
+                _ => SelectionType::Other,
             },
-            // We need to transform JSON arrays to the special RethinkDB representation, which uses
-            // the MAKE_ARRAY term.
-            &Tree::Datum(Json::Array(ref array)) => Json::Array(vec![
-                Term_TermType::MAKE_ARRAY.value().to_json(),
-                Json::Array(array.iter().cloned().collect::<Vec<Json>>()),
-            ]),
-            &Tree::Datum(ref json) => json.clone(),
+            // Variables, and terms evaluating to one of their arguments or to what a function
+            // returns.
+            (Term_TermType::VAR, _) |
+            (Term_TermType::IMPLICIT_VAR, _) |
+            (Term_TermType::FUNCALL, _) |
+            (Term_TermType::BRANCH, _) |
+            (Term_TermType::DEFAULT, _) |
+            (Term_TermType::BRACKET, _) |
+            (Term_TermType::ARGS, _) => SelectionType::Unknown,
+            _ => SelectionType::Other,
         }
     }
 }
@@ -0,0 +1,422 @@
+use base64;
+use serde::de::{self, Deserialize, DeserializeOwned, Deserializer};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use serde_json::{self, Map, Value};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// ReQL has a few types JSON can't express, which travel as objects tagged with their type:
+//
+// ```json
+// {"$reql_type$": "TIME", "epoch_time": 1514764800.0, "timezone": "+01:00"}
+// {"$reql_type$": "BINARY", "data": "<base64>"}
+// {"$reql_type$": "GEOMETRY", "type": "Point", "coordinates": [-122.4, 37.8]}
+// {"$reql_type$": "GROUPED_DATA", "data": [[<group>, <reduction>], ...]}
+// ```
+//
+// The types below read and write those objects, so they can go anywhere in documents that are
+// inserted or decoded.  `Literal` borrows the same tagging to mark values that have to be sent as
+// LITERAL terms.
+
+const REQL_TYPE: &str = "$reql_type$";
+
+/// How a pseudo-type comes back in results.  `Native` is what the types in this module expect.
+/// `Raw` leaves the tagged objects alone, for decoding them by hand.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Native,
+    Raw,
+}
+
+impl Format {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Format::Native => "native",
+            Format::Raw => "raw",
+        }
+    }
+}
+
+/// Checks that a pseudo-type object is tagged with `expected`.
+fn check_tag<E: de::Error>(object: &Map<String, Value>, expected: &str) -> Result<(), E> {
+    match object.get(REQL_TYPE) {
+        Some(&Value::String(ref tag)) if tag == expected => Ok(()),
+        Some(tag) => Err(E::custom(format!("expected a {} pseudo-type, got {}", expected, tag))),
+        None => Err(E::custom(format!("expected a {} pseudo-type, got a plain object", expected))),
+    }
+}
+
+/// Takes a field out of a pseudo-type object and decodes it.
+fn take_field<T: DeserializeOwned, E: de::Error>(object: &mut Map<String, Value>, field: &'static str) -> Result<T, E> {
+    match object.remove(field) {
+        Some(value) => serde_json::from_value(value).map_err(E::custom),
+        None => Err(E::missing_field(field)),
+    }
+}
+
+fn pseudo_type<'de, D: Deserializer<'de>>(deserializer: D, expected: &str) -> Result<Map<String, Value>, D::Error> {
+    let object = Map::deserialize(deserializer)?;
+    check_tag::<D::Error>(&object, expected)?;
+
+    Ok(object)
+}
+
+/// A point in time, along with the UTC offset it was given in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Time {
+    /// Seconds since the Unix epoch, with millisecond precision.
+    pub epoch_time: f64,
+    /// Seconds east of UTC.
+    pub offset: i32,
+}
+
+impl Time {
+    pub fn new(epoch_time: f64, offset: i32) -> Time {
+        Time {
+            epoch_time: epoch_time,
+            offset: offset,
+        }
+    }
+
+    /// The current time, in UTC.
+    pub fn now() -> Time {
+        Time::from_system_time(SystemTime::now(), 0)
+    }
+
+    pub fn from_system_time(time: SystemTime, offset: i32) -> Time {
+        let epoch_time = match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as f64 + since.subsec_nanos() as f64 / 1e9,
+            Err(error) => {
+                let before = error.duration();
+                -(before.as_secs() as f64 + before.subsec_nanos() as f64 / 1e9)
+            },
+        };
+
+        Time::new(epoch_time, offset)
+    }
+
+    pub fn to_system_time(&self) -> SystemTime {
+        let since = Duration::from_millis((self.epoch_time.abs() * 1000.0).round() as u64);
+        if self.epoch_time < 0.0 {
+            UNIX_EPOCH - since
+        } else {
+            UNIX_EPOCH + since
+        }
+    }
+
+    /// The offset the way the server writes it, e.g. `+01:00` or `-07:30`.
+    pub fn timezone(&self) -> String {
+        let sign = if self.offset < 0 { '-' } else { '+' };
+        let minutes = self.offset.abs() / 60;
+
+        format!("{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+    }
+
+    /// Parses `Z`, `+HH:MM` or `-HH:MM` into seconds east of UTC.
+    fn parse_timezone(timezone: &str) -> Option<i32> {
+        if timezone == "Z" {
+            return Some(0);
+        }
+        // Slicing by byte below only works on single-byte characters.
+        if !timezone.is_ascii() || timezone.len() != 6 || &timezone[3..4] != ":" {
+            return None;
+        }
+
+        let sign = match &timezone[..1] {
+            "+" => 1,
+            "-" => -1,
+            _ => return None,
+        };
+        let hours = timezone[1..3].parse::<i32>().ok()?;
+        let minutes = timezone[4..6].parse::<i32>().ok()?;
+
+        Some(sign * (hours * 3600 + minutes * 60))
+    }
+}
+
+impl Serialize for Time {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut object = serializer.serialize_map(Some(3))?;
+        object.serialize_entry(REQL_TYPE, "TIME")?;
+        object.serialize_entry("epoch_time", &self.epoch_time)?;
+        object.serialize_entry("timezone", &self.timezone())?;
+
+        object.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Time {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Time, D::Error> {
+        let mut object = pseudo_type(deserializer, "TIME")?;
+        let epoch_time = take_field::<f64, D::Error>(&mut object, "epoch_time")?;
+        let timezone = take_field::<String, D::Error>(&mut object, "timezone")?;
+        match Time::parse_timezone(&timezone) {
+            Some(offset) => Ok(Time::new(epoch_time, offset)),
+            None => Err(de::Error::custom(format!("invalid timezone: {}", timezone))),
+        }
+    }
+}
+
+/// Bytes, which travel base64 encoded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Binary(pub Vec<u8>);
+
+impl Serialize for Binary {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut object = serializer.serialize_map(Some(2))?;
+        object.serialize_entry(REQL_TYPE, "BINARY")?;
+        object.serialize_entry("data", &base64::encode(&self.0))?;
+
+        object.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Binary {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Binary, D::Error> {
+        let mut object = pseudo_type(deserializer, "BINARY")?;
+        let data = take_field::<String, D::Error>(&mut object, "data")?;
+        match base64::decode(&data) {
+            Ok(bytes) => Ok(Binary(bytes)),
+            Err(error) => Err(de::Error::custom(error)),
+        }
+    }
+}
+
+/// A longitude and a latitude, in degrees.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Point {
+    pub longitude: f64,
+    pub latitude: f64,
+}
+
+impl Point {
+    pub fn new(longitude: f64, latitude: f64) -> Point {
+        Point {
+            longitude: longitude,
+            latitude: latitude,
+        }
+    }
+}
+
+impl Serialize for Point {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        [self.longitude, self.latitude].serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Point {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Point, D::Error> {
+        let (longitude, latitude) = <(f64, f64)>::deserialize(deserializer)?;
+
+        Ok(Point::new(longitude, latitude))
+    }
+}
+
+/// The GeoJSON shapes the server stores.  The rings of a polygon are closed: their last point is
+/// the same as their first.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Geometry {
+    Point(Point),
+    Line(Vec<Point>),
+    /// The outer ring, then any holes.
+    Polygon(Vec<Vec<Point>>),
+}
+
+impl Serialize for Geometry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut object = serializer.serialize_map(Some(3))?;
+        object.serialize_entry(REQL_TYPE, "GEOMETRY")?;
+        match self {
+            &Geometry::Point(ref point) => {
+                object.serialize_entry("type", "Point")?;
+                object.serialize_entry("coordinates", point)?;
+            },
+            &Geometry::Line(ref points) => {
+                object.serialize_entry("type", "LineString")?;
+                object.serialize_entry("coordinates", points)?;
+            },
+            &Geometry::Polygon(ref rings) => {
+                object.serialize_entry("type", "Polygon")?;
+                object.serialize_entry("coordinates", rings)?;
+            },
+        }
+
+        object.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Geometry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Geometry, D::Error> {
+        let mut object = pseudo_type(deserializer, "GEOMETRY")?;
+        let shape = take_field::<String, D::Error>(&mut object, "type")?;
+        match shape.as_str() {
+            "Point" => Ok(Geometry::Point(take_field::<_, D::Error>(&mut object, "coordinates")?)),
+            "LineString" => Ok(Geometry::Line(take_field::<_, D::Error>(&mut object, "coordinates")?)),
+            "Polygon" => Ok(Geometry::Polygon(take_field::<_, D::Error>(&mut object, "coordinates")?)),
+            other => Err(de::Error::custom(format!("unsupported geometry type: {}", other))),
+        }
+    }
+}
+
+/// One group of a `group` query, and what its elements were reduced to.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Group<K, V> {
+    pub group: K,
+    pub reduction: V,
+}
+
+/// The result of a `group` query that wasn't ungrouped.  It decodes from the GROUPED_DATA
+/// pseudo-type as well as from the list of groups it is turned into with `Format::Native`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Grouped<K, V>(pub Vec<Group<K, V>>);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum GroupedForm<K, V> {
+    Native(Vec<Group<K, V>>),
+    Raw {
+        data: Vec<(K, V)>,
+    },
+}
+
+impl<K, V> Grouped<K, V> {
+    /// Each group's key, with what its elements were reduced to.
+    pub fn into_pairs(self) -> Vec<(K, V)> {
+        self.0.into_iter().map(|group| (group.group, group.reduction)).collect()
+    }
+}
+
+impl<K, V> From<Grouped<K, V>> for Vec<(K, V)> {
+    fn from(grouped: Grouped<K, V>) -> Vec<(K, V)> {
+        grouped.into_pairs()
+    }
+}
+
+impl<'de, K: Deserialize<'de>, V: Deserialize<'de>> Deserialize<'de> for Grouped<K, V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Grouped<K, V>, D::Error> {
+        match GroupedForm::deserialize(deserializer)? {
+            GroupedForm::Native(groups) => Ok(Grouped(groups)),
+            GroupedForm::Raw { data } => Ok(Grouped(data.into_iter()
+                .map(|(group, reduction)| Group { group: group, reduction: reduction })
+                .collect())),
+        }
+    }
+}
+
+/// Sets a field to exactly `value` in `update` and `merge`, instead of merging into what's there,
+/// like `r.literal(...)` does.  `Literal(None)` removes the field.
+///
+/// It can go anywhere in a document, and is sent as a LITERAL term, so it never comes back in
+/// results.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Literal<T>(pub Option<T>);
+
+impl<T: Serialize> Serialize for Literal<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut object = serializer.serialize_map(None)?;
+        object.serialize_entry(REQL_TYPE, "LITERAL")?;
+        if let Some(ref value) = self.0 {
+            object.serialize_entry("value", value)?;
+        }
+
+        object.end()
+    }
+}
+
+/// Which fields `pluck`, `without`, `has_fields` and `with_fields` pick: a field, or fields nested
+/// under one, like `{"address": ["city", "zip"]}` in the other drivers.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Field {
+    Name(String),
+    Nested(String, Vec<Field>),
+}
+
+impl Field {
+    pub fn nested<T: Into<Field>>(name: &str, fields: Vec<T>) -> Field {
+        Field::Nested(name.to_owned(), fields.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<'a> From<&'a str> for Field {
+    fn from(name: &'a str) -> Field {
+        Field::Name(name.to_owned())
+    }
+}
+
+impl Serialize for Field {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            &Field::Name(ref name) => serializer.serialize_str(name),
+            &Field::Nested(ref name, ref fields) => {
+                let mut object = serializer.serialize_map(Some(1))?;
+                object.serialize_entry(name, fields)?;
+
+                object.end()
+            },
+        }
+    }
+}
+
+/// If `object` is a serialized `Literal`, the value it holds.
+pub fn literal_value(object: &Map<String, Value>) -> Option<Option<&Value>> {
+    match object.get(REQL_TYPE) {
+        Some(&Value::String(ref tag)) if tag == "LITERAL" => Some(object.get("value")),
+        _ => None,
+    }
+}
+
+/// Turns every GROUPED_DATA pseudo-type in `json` into an array of `{"group", "reduction"}`
+/// objects, which is how grouped results are presented with `Format::Native`.
+pub fn native_groups(json: Value) -> Value {
+    map_groups(json, &|group, reduction| {
+        let mut object = Map::new();
+        object.insert("group".to_owned(), group);
+        object.insert("reduction".to_owned(), reduction);
+        Value::Object(object)
+    })
+}
+
+/// Turns every GROUPED_DATA pseudo-type in `json` into an array of `[group, reduction]` pairs, so
+/// grouped results can also be decoded straight into a `Vec<(K, V)>`.
+pub fn group_pairs(json: Value) -> Value {
+    map_groups(json, &|group, reduction| Value::Array(vec![group, reduction]))
+}
+
+/// Whether there is a GROUPED_DATA pseudo-type anywhere in `json`.
+pub fn has_groups(json: &Value) -> bool {
+    match json {
+        &Value::Array(ref array) => array.iter().any(has_groups),
+        &Value::Object(ref object) => match object.get(REQL_TYPE) {
+            Some(&Value::String(ref tag)) if tag == "GROUPED_DATA" => true,
+            _ => object.values().any(has_groups),
+        },
+        _ => false,
+    }
+}
+
+fn map_groups(json: Value, group: &dyn Fn(Value, Value) -> Value) -> Value {
+    match json {
+        Value::Array(array) => Value::Array(array.into_iter().map(|value| map_groups(value, group)).collect()),
+        Value::Object(mut object) => {
+            let grouped = match object.get(REQL_TYPE) {
+                Some(&Value::String(ref tag)) => tag == "GROUPED_DATA",
+                _ => false,
+            };
+            if !grouped {
+                return Value::Object(object.into_iter().map(|(key, value)| (key, map_groups(value, group))).collect());
+            }
+
+            let data = match object.remove("data") {
+                Some(Value::Array(data)) => data,
+                _ => vec![],
+            };
+            Value::Array(data.into_iter().map(|pair| match pair {
+                Value::Array(mut pair) if pair.len() == 2 => {
+                    let reduction = map_groups(pair.remove(1), group);
+                    group(map_groups(pair.remove(0), group), reduction)
+                },
+                _ => Value::Object(Map::new()),
+            }).collect())
+        },
+        other => other,
+    }
+}
@@ -1,13 +1,90 @@
 #[cfg(test)]
 #[warn(unused_imports)]
 
-use connection::Connection;
+use connection::{ConnectOptions, Connection};
+
+/// Must have RethinkDB running and listening for client driver connections, on localhost:28015
+/// unless `RETHINKDB_HOST` and `RETHINKDB_PORT` say otherwise.
+#[cfg(test)]
+fn test_options() -> ConnectOptions {
+    use std::env;
+
+    let host = env::var("RETHINKDB_HOST").unwrap_or("localhost".to_owned());
+    let port = env::var("RETHINKDB_PORT").ok().and_then(|port| port.parse().ok()).unwrap_or(28015);
+
+    ConnectOptions::new(&host, port)
+}
+
+/// A stand-in for RethinkDB on a local port, for tests that script the server's side of a
+/// connection.  It takes the V0_4 handshake, which needs no SCRAM exchange, and hands the first
+/// `connections` clients to `serve`, one after the other.
+#[cfg(test)]
+fn stub_server<F>(connections: usize, serve: F) -> ConnectOptions
+    where F: Fn(Stub<::std::net::TcpStream>) + Send + 'static
+{
+    use protocol::HandshakeVersion;
+    use std::net::TcpListener;
+    use std::thread;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for stream in listener.incoming().take(connections) {
+            serve(Stub::accept(stream.unwrap()));
+        }
+    });
+
+    ConnectOptions::new("127.0.0.1", port).handshake(HandshakeVersion::V0_4)
+}
+
+/// The server's end of a connection to `stub_server`.
+#[cfg(test)]
+struct Stub<S> {
+    stream: S,
+}
+
+#[cfg(test)]
+impl<S: ::std::io::Read + ::std::io::Write> Stub<S> {
+    /// Takes the client's V0_4 handshake, whatever its auth key.
+    fn accept(mut stream: S) -> Stub<S> {
+        use byteorder::{LittleEndian, ReadBytesExt};
+
+        let _version = stream.read_u32::<LittleEndian>().unwrap();
+        let mut auth_key = vec![0; stream.read_u32::<LittleEndian>().unwrap() as usize];
+        stream.read_exact(&mut auth_key).unwrap();
+        let _protocol = stream.read_u32::<LittleEndian>().unwrap();
+        stream.write_all(b"SUCCESS\0").unwrap();
+        stream.flush().unwrap();
+
+        Stub { stream: stream }
+    }
+
+    /// The token and the query of the next frame, or `None` once the client has hung up.
+    fn query(&mut self) -> Option<(u64, ::serde_json::Value)> {
+        use byteorder::{LittleEndian, ReadBytesExt};
+        use serde_json;
+
+        let token = self.stream.read_u64::<LittleEndian>().ok()?;
+        let mut query = vec![0; self.stream.read_u32::<LittleEndian>().ok()? as usize];
+        self.stream.read_exact(&mut query).ok()?;
+
+        Some((token, serde_json::from_slice(&query).unwrap()))
+    }
+
+    /// Answers the query sent under `token` with `response`, e.g. `{"t":1,"r":[true]}`.
+    fn respond(&mut self, token: u64, response: &str) {
+        use byteorder::{LittleEndian, WriteBytesExt};
+
+        self.stream.write_u64::<LittleEndian>(token).unwrap();
+        self.stream.write_u32::<LittleEndian>(response.len() as u32).unwrap();
+        self.stream.write_all(response.as_bytes()).unwrap();
+        self.stream.flush().unwrap();
+    }
+}
 
 #[test]
 fn test_connect() {
-    // Must have RethinkDB running and listening for client driver connections on port 28015.
-    // TODO: Make this configurable.
-    match Connection::connect("localhost", 28015, "admin", "") {
+    match Connection::connect_with(test_options()) {
         Ok(_) => assert!(true),
         Err(error) => {
             println!("{}", error);
@@ -16,6 +93,1132 @@ fn test_connect() {
     };
 }
 
+#[test]
+fn test_query_envelope() {
+    use reql::query::Query;
+    use reql::tree::Tree;
+    use ql2::Term_TermType;
+    use serde_json::{self, Value};
+
+    let start = Query::start(Tree::query(Term_TermType::DB_LIST, vec![]));
+    assert_eq!(serde_json::to_string(&start).unwrap(), "[1,[59]]");
+
+    // Arguments go in an array of their own.
+    let create = Query::start(Tree::query(Term_TermType::DB_CREATE, vec![Tree::Datum(Value::from("app"))]));
+    assert_eq!(serde_json::to_string(&create).unwrap(), r#"[1,[57,["app"]]]"#);
+
+    assert_eq!(serde_json::to_string(&Query::continue_query()).unwrap(), "[2]");
+    assert_eq!(serde_json::to_string(&Query::stop()).unwrap(), "[3]");
+    assert_eq!(serde_json::to_string(&Query::noreply_wait()).unwrap(), "[4]");
+    assert_eq!(serde_json::to_string(&Query::server_info()).unwrap(), "[5]");
+}
+
+#[test]
+fn test_global_optargs() {
+    use reql::query::Query;
+    use reql::tree::Tree;
+    use ql2::Term_TermType;
+    use serde_json;
+
+    let options = ConnectOptions::new("localhost", 28015).db("app").durability("soft");
+    let mut start = Query::start(Tree::query(Term_TermType::TABLE_LIST, vec![]));
+    start.global_optargs = options.query_optargs();
+    assert_eq!(serde_json::to_string(&start).unwrap(), r#"[1,[62],{"db":[14,["app"]],"durability":"soft"}]"#);
+}
+
+#[test]
+fn test_response_decoding() {
+    use error::Error;
+    use reql::response::{FeedType, Response};
+    use ql2::{Response_ErrorType, Response_ResponseNote, Response_ResponseType};
+    use serde_json;
+
+    let json = serde_json::from_str(r#"{"t": 3, "r": [1, 2], "n": [1]}"#).unwrap();
+    let response = Response::from_json(json).ok().unwrap();
+    assert_eq!(response.response_type, Response_ResponseType::SUCCESS_PARTIAL);
+    assert_eq!(response.results.len(), 2);
+    assert_eq!(response.notes, vec![Response_ResponseNote::SEQUENCE_FEED]);
+    assert_eq!(response.feed_type(), Some(FeedType::Sequence));
+    assert!(!response.includes_states());
+    assert!(response.is_partial());
+    assert!(response.to_error().is_none());
+
+    let json = serde_json::from_str(r#"{"t": 18, "e": 3100000, "r": ["Table `x` does not exist."], "b": [0]}"#).unwrap();
+    let response = Response::from_json(json).ok().unwrap();
+    assert_eq!(response.error_type, Some(Response_ErrorType::NON_EXISTENCE));
+    assert!(response.backtrace.is_some());
+    match response.to_error() {
+        Some(Error::ReqlRuntimeError(Some(Response_ErrorType::NON_EXISTENCE), ref message)) => assert_eq!(message, "Table `x` does not exist."),
+        _ => assert!(false),
+    }
+}
+
+#[test]
+fn test_result_decoding() {
+    use error::Error;
+    use reql::response::Response;
+    use serde_json;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Person {
+        name: String,
+        age: u32,
+    }
+
+    let json = serde_json::from_str(r#"{"t": 1, "r": [{"name": "Nacho", "age": 6}]}"#).unwrap();
+    let person: Person = Response::from_json(json).ok().unwrap().into_result().ok().unwrap();
+    assert_eq!(person, Person { name: "Nacho".to_owned(), age: 6 });
+
+    let json = serde_json::from_str(r#"{"t": 2, "r": [1, 2, 3]}"#).unwrap();
+    let numbers: Vec<u32> = Response::from_json(json).ok().unwrap().into_result().ok().unwrap();
+    assert_eq!(numbers, vec![1, 2, 3]);
+
+    let json = serde_json::from_str(r#"{"t": 1, "r": ["Nacho"]}"#).unwrap();
+    match Response::from_json(json).ok().unwrap().into_result::<Person>() {
+        Err(Error::DecodeError(_)) => {},
+        _ => assert!(false),
+    }
+}
+
+#[test]
+fn test_cursor_batches() {
+    use reql::r::r;
+    use serde_json::Value;
+    use std::sync::mpsc;
+
+    // The rest of the results come with CONTINUE on the same token, even after an empty batch.
+    let (sender, continued) = mpsc::channel();
+    let options = stub_server(1, move |mut stub| {
+        let (token, _) = stub.query().unwrap();
+        stub.respond(token, r#"{"t":3,"r":[1,2]}"#);
+        for response in vec![r#"{"t":3,"r":[]}"#, r#"{"t":2,"r":[3]}"#] {
+            let (next, query) = stub.query().unwrap();
+            sender.send((next == token, query)).unwrap();
+            stub.respond(next, response);
+        }
+    });
+    let conn = Connection::connect_with(options).ok().unwrap();
+
+    let cursor = conn.run_cursor::<u32, _>(r.table("numbers")).ok().unwrap();
+    assert_eq!(cursor.collect::<Result<Vec<u32>, _>>().ok(), Some(vec![1, 2, 3]));
+    assert_eq!(continued.iter().collect::<Vec<(bool, Value)>>(), vec![(true, Value::from(vec![2])), (true, Value::from(vec![2]))]);
+}
+
+#[test]
+fn test_cursor_stop() {
+    use reql::r::r;
+    use serde_json::Value;
+    use std::sync::mpsc;
+
+    // Dropping a cursor with batches left on the server stops the query, without waiting for an
+    // answer that may never come.
+    let (sender, stopped) = mpsc::channel();
+    let options = stub_server(1, move |mut stub| {
+        let (token, _) = stub.query().unwrap();
+        stub.respond(token, r#"{"t":3,"r":[1,2]}"#);
+        let (next, query) = stub.query().unwrap();
+        sender.send((next == token, query)).unwrap();
+        while stub.query().is_some() {}
+    });
+    let conn = Connection::connect_with(options).ok().unwrap();
+
+    {
+        let mut cursor = conn.run_cursor::<u32, _>(r.table("numbers")).ok().unwrap();
+        assert_eq!(cursor.next().and_then(Result::ok), Some(1));
+    }
+    assert_eq!(stopped.recv().ok(), Some((true, Value::from(vec![3]))));
+}
+
+#[cfg(test)]
+fn run_tables_concurrently(options: ConnectOptions) -> Vec<Result<String, ::error::Error>> {
+    use reql::r::r;
+    use std::sync::Arc;
+    use std::thread;
+
+    let conn = Arc::new(Connection::connect_with(options).ok().unwrap());
+    let threads = vec!["a", "b"].into_iter().map(|name| {
+        let conn = conn.clone();
+        thread::spawn(move || conn.run::<String, _>(r.table(name)))
+    }).collect::<Vec<_>>();
+
+    threads.into_iter().map(|thread| thread.join().unwrap()).collect()
+}
+
+#[test]
+fn test_reader_out_of_order() {
+    // Both queries are in flight at once, and each caller gets the answer to its own, whatever
+    // order the answers come in.  The stub answers with the name of the table queried.
+    let options = stub_server(1, |mut stub| {
+        let mut queries = vec![stub.query().unwrap(), stub.query().unwrap()];
+        queries.reverse();
+        for (token, query) in queries {
+            stub.respond(token, &format!(r#"{{"t":1,"r":[{}]}}"#, query[1][1][0]));
+        }
+        while stub.query().is_some() {}
+    });
+
+    let results = run_tables_concurrently(options);
+    assert_eq!(results.into_iter().map(|result| result.ok()).collect::<Vec<_>>(), vec![Some("a".to_owned()), Some("b".to_owned())]);
+}
+
+#[test]
+fn test_reader_lost() {
+    use error::Error;
+
+    // Everyone waiting on an answer hears about the socket closing.
+    let options = stub_server(1, |mut stub| {
+        let _ = (stub.query().unwrap(), stub.query().unwrap());
+    });
+
+    for result in run_tables_concurrently(options) {
+        match result {
+            Err(Error::ConnectionLost(_)) => {},
+            _ => assert!(false),
+        }
+    }
+}
+
+/// Answers every query with the name of the table it reads, like `test_reader_out_of_order`'s stub.
+#[cfg(test)]
+fn serve_table_names(mut stub: Stub<::std::net::TcpStream>) {
+    while let Some((token, query)) = stub.query() {
+        stub.respond(token, &format!(r#"{{"t":1,"r":[{}]}}"#, query[1][1][0]));
+    }
+}
+
+/// Waits for the connection to notice that the server went away.
+#[cfg(test)]
+fn wait_until_closed(conn: &Connection) {
+    use std::thread;
+    use std::time::Duration;
+
+    for _ in 0..500 {
+        if conn.is_closed() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(false);
+}
+
+#[test]
+fn test_query_too_large() {
+    use error::Error;
+    use protocol;
+    use reql::query::Query;
+    use reql::r::r;
+    use reql::tree::IntoTree;
+
+    let query = Query::start(r.table("x".repeat(100)).into_tree().ok().unwrap());
+    match protocol::encode_query(1, &query, 64) {
+        Err(Error::QueryTooLarge(len)) => assert_eq!(len, 113),
+        _ => assert!(false),
+    }
+    let frame = protocol::encode_query(1, &query, protocol::MAX_QUERY_LEN).ok().unwrap();
+    assert_eq!(protocol::decode_header(&frame[..protocol::HEADER_LEN]), (1, 113));
+}
+
+#[test]
+fn test_reconnect() {
+    use backoff::Backoff;
+    use reql::r::r;
+    use std::time::Duration;
+
+    // The first server answers one query and goes away for good, so reconnecting moves on to the
+    // seed host.
+    let first = stub_server(1, |mut stub| {
+        let (token, _) = stub.query().unwrap();
+        stub.respond(token, r#"{"t":1,"r":["first"]}"#);
+    });
+    let seed = stub_server(1, serve_table_names);
+    let options = first
+        .seed_host("127.0.0.1", seed.port)
+        .reconnect(Backoff::default().initial_delay(Duration::from_millis(10)).max_retries(Some(1)));
+    let conn = Connection::connect_with(options).ok().unwrap();
+
+    assert_eq!(conn.run::<String, _>(r.table("users")).ok(), Some("first".to_owned()));
+    wait_until_closed(&conn);
+    assert_eq!(conn.run::<String, _>(r.table("users")).ok(), Some("users".to_owned()));
+}
+
+#[test]
+fn test_reconnect_gives_up() {
+    use backoff::Backoff;
+    use error::Error;
+    use reql::r::r;
+    use std::time::Duration;
+
+    let options = stub_server(1, |mut stub| {
+        let (token, _) = stub.query().unwrap();
+        stub.respond(token, r#"{"t":1,"r":["first"]}"#);
+    });
+    let options = options.reconnect(Backoff::default().initial_delay(Duration::from_millis(10)).max_retries(Some(2)));
+    let conn = Connection::connect_with(options).ok().unwrap();
+
+    assert!(conn.run::<String, _>(r.table("users")).is_ok());
+    wait_until_closed(&conn);
+    match conn.run::<String, _>(r.table("users")) {
+        Err(Error::ConnectionLost(_)) => {},
+        _ => assert!(false),
+    }
+}
+
+/// Answers the first query with two results, and the CONTINUE that follows with the last one.
+#[cfg(all(test, feature = "async"))]
+fn serve_batches(mut stub: Stub<::std::net::TcpStream>) {
+    let (token, _) = stub.query().unwrap();
+    stub.respond(token, r#"{"t":3,"r":[1,2]}"#);
+    let (token, _) = stub.query().unwrap();
+    stub.respond(token, r#"{"t":2,"r":[3]}"#);
+    while stub.query().is_some() {}
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_async_run() {
+    use async_connection::AsyncConnection;
+    use futures::Future;
+    use reql::r::r;
+    use tokio::runtime::current_thread::Runtime;
+
+    let options = stub_server(1, serve_batches);
+    let mut runtime = Runtime::new().unwrap();
+
+    let numbers = AsyncConnection::connect_with(&options)
+        .and_then(|conn| conn.run::<Vec<u32>, _>(r.table("numbers")));
+    assert_eq!(runtime.block_on(numbers).ok(), Some(vec![1, 2, 3]));
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_async_run_cursor() {
+    use async_connection::AsyncConnection;
+    use futures::{Future, Stream};
+    use reql::r::r;
+    use tokio::runtime::current_thread::Runtime;
+
+    let options = stub_server(1, serve_batches);
+    let mut runtime = Runtime::new().unwrap();
+
+    let numbers = AsyncConnection::connect_with(&options)
+        .and_then(|conn| conn.run_cursor::<u32, _>(r.table("numbers")))
+        .and_then(|cursor| cursor.collect());
+    assert_eq!(runtime.block_on(numbers).ok(), Some(vec![1, 2, 3]));
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_async_timeout() {
+    use async_connection::AsyncConnection;
+    use error::Error;
+    use futures::Future;
+    use reql::r::r;
+    use std::time::Duration;
+    use tokio::runtime::current_thread::Runtime;
+
+    // The stub never answers.
+    let options = stub_server(1, |mut stub| while stub.query().is_some() {})
+        .read_timeout(Duration::from_millis(50));
+    let mut runtime = Runtime::new().unwrap();
+
+    let numbers = AsyncConnection::connect_with(&options)
+        .and_then(|conn| conn.run::<Vec<u32>, _>(r.table("numbers")));
+    match runtime.block_on(numbers) {
+        Err(Error::ResponseTimeout(timeout)) => assert_eq!(timeout, Duration::from_millis(50)),
+        _ => assert!(false),
+    }
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_async_reconnect() {
+    use async_connection::AsyncConnection;
+    use backoff::Backoff;
+    use reql::r::r;
+    use std::net::TcpListener;
+    use std::time::{Duration, Instant};
+    use tokio::runtime::current_thread::Runtime;
+    use tokio::timer::Delay;
+
+    // Nothing listens on the first host, so the connection is made to the second.  That one goes
+    // away after one query, so reconnecting moves on to the third.
+    let down = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let first = stub_server(1, |mut stub| {
+        let (token, _) = stub.query().unwrap();
+        stub.respond(token, r#"{"t":1,"r":["first"]}"#);
+    });
+    let seed = stub_server(1, serve_table_names);
+    let options = ConnectOptions::new("127.0.0.1", down)
+        .handshake(first.handshake)
+        .seed_host("127.0.0.1", first.port)
+        .seed_host("127.0.0.1", seed.port)
+        .reconnect(Backoff::default().initial_delay(Duration::from_millis(10)).max_retries(Some(1)));
+    let mut runtime = Runtime::new().unwrap();
+
+    let conn = runtime.block_on(AsyncConnection::connect_with(&options)).ok().unwrap();
+    assert_eq!(runtime.block_on(conn.run::<String, _>(r.table("users"))).ok(), Some("first".to_owned()));
+
+    // Give the reader a chance to see the socket close.
+    runtime.block_on(Delay::new(Instant::now() + Duration::from_millis(50))).unwrap();
+    assert_eq!(runtime.block_on(conn.run::<String, _>(r.table("users"))).ok(), Some("users".to_owned()));
+}
+
+/// Answers every NOREPLY_WAIT, which is how the pool checks on V0_4 connections, and reports each
+/// connection it takes to `accepted`.
+#[cfg(test)]
+fn serve_health_checks(accepted: ::std::sync::mpsc::Sender<()>) -> impl Fn(Stub<::std::net::TcpStream>) + Send + 'static {
+    move |mut stub| {
+        accepted.send(()).unwrap();
+        while let Some((token, _)) = stub.query() {
+            stub.respond(token, r#"{"t":4,"r":[]}"#);
+        }
+    }
+}
+
+#[test]
+fn test_pool_checkout() {
+    use error::Error;
+    use pool::{Pool, PoolOptions};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let (sender, accepted) = mpsc::channel();
+    let options = stub_server(2, serve_health_checks(sender));
+
+    match Pool::new(options.clone(), PoolOptions::default().min_size(2).max_size(1)) {
+        Err(Error::ServerError(_)) => {},
+        _ => assert!(false),
+    }
+
+    let pool = Pool::new(options, PoolOptions::default().max_size(1).checkout_timeout(Duration::from_millis(50))).ok().unwrap();
+    {
+        let conn = pool.get().ok().unwrap();
+        assert!(!conn.is_closed());
+
+        // The only connection is checked out.
+        match pool.get() {
+            Err(Error::PoolTimeout(timeout)) => assert_eq!(timeout, Duration::from_millis(50)),
+            _ => assert!(false),
+        }
+    }
+
+    // Once it's back, it's handed out again rather than replaced.
+    assert!(pool.get().is_ok());
+    assert!(accepted.recv().is_ok());
+    assert!(accepted.recv_timeout(Duration::from_millis(50)).is_err());
+}
+
+#[test]
+fn test_pool_idle() {
+    use pool::{Pool, PoolOptions};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    let (sender, accepted) = mpsc::channel();
+    let options = stub_server(2, serve_health_checks(sender));
+    let pool_options = PoolOptions::default()
+        .min_size(0)
+        .max_size(1)
+        .idle_timeout(Some(Duration::from_millis(20)));
+    let pool = Pool::new(options, pool_options).ok().unwrap();
+
+    drop(pool.get().ok().unwrap());
+    assert!(accepted.recv().is_ok());
+
+    // The first connection sat idle for too long, so it was closed and another one opened.
+    thread::sleep(Duration::from_millis(40));
+    assert!(pool.get().is_ok());
+    assert!(accepted.recv_timeout(Duration::from_secs(5)).is_ok());
+}
+
+#[test]
+fn test_pool_health_check() {
+    use pool::{Pool, PoolOptions};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    // The first connection stops answering, so the health check gives up on it after the checkout
+    // timeout, and the pool opens a second one.
+    let first = AtomicBool::new(true);
+    let options = stub_server(2, move |mut stub| {
+        let silent = first.swap(false, Ordering::SeqCst);
+        while let Some((token, _)) = stub.query() {
+            if !silent {
+                stub.respond(token, r#"{"t":4,"r":[]}"#);
+            }
+        }
+    });
+    let pool = Pool::new(options, PoolOptions::default().max_size(1).checkout_timeout(Duration::from_millis(50))).ok().unwrap();
+
+    let conn = pool.get().ok().unwrap();
+    assert!(conn.noreply_wait().is_ok());
+}
+
+#[test]
+fn test_pool_closed_connection() {
+    use backoff::Backoff;
+    use pool::{Pool, PoolOptions};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // The server hangs up on the idle connection and goes away.  Checking out drops the connection
+    // instead of sitting through the reconnect backoff, and fails to open a new one.
+    let options = stub_server(1, |_| {})
+        .reconnect(Backoff::default().initial_delay(Duration::from_secs(5)).max_retries(Some(1)));
+    let pool = Pool::new(options, PoolOptions::default().checkout_timeout(Duration::from_secs(1))).ok().unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    let started = Instant::now();
+    assert!(pool.get().is_err());
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn test_admin_results() {
+    use reql::results::{TableCreated, TableStatus};
+    use serde_json;
+
+    let created: TableCreated = serde_json::from_str(r#"{
+        "tables_created": 1,
+        "config_changes": [{
+            "old_val": null,
+            "new_val": {
+                "db": "test", "durability": "hard", "id": "20ea60d4-3b76-4817-8828-98a236df0297",
+                "indexes": [], "name": "person", "primary_key": "id", "write_acks": "majority",
+                "shards": [{"nonvoting_replicas": [], "primary_replica": "alpha", "replicas": ["alpha", "beta"]}]
+            }
+        }]
+    }"#).unwrap();
+    assert_eq!(created.tables_created, 1);
+    let config = created.config_changes[0].new_val.as_ref().unwrap();
+    assert_eq!(config.shards[0].replicas, vec!["alpha".to_owned(), "beta".to_owned()]);
+
+    let status: TableStatus = serde_json::from_str(r#"{
+        "db": "test", "id": "20ea60d4-3b76-4817-8828-98a236df0297", "name": "person", "raft_leader": "alpha",
+        "shards": [{"primary_replicas": ["alpha"], "replicas": [{"server": "alpha", "state": "ready"}]}],
+        "status": {"all_replicas_ready": true, "ready_for_outdated_reads": true, "ready_for_reads": true, "ready_for_writes": true}
+    }"#).unwrap();
+    assert!(status.status.all_replicas_ready);
+    assert_eq!(status.shards[0].replicas[0].state, "ready");
+}
+
+#[test]
+fn test_table_create() {
+    use admin::TableOptions;
+    use serde_json::Value;
+    use std::sync::mpsc;
+
+    let (sender, queries) = mpsc::channel();
+    let options = stub_server(1, move |mut stub| {
+        while let Some((token, query)) = stub.query() {
+            sender.send(query).unwrap();
+            stub.respond(token, r#"{"t":1,"r":[{"tables_created":1,"config_changes":[]}]}"#);
+        }
+    });
+    let conn = Connection::connect_with(options).ok().unwrap();
+
+    let table_options = TableOptions::default().shards(2).replicas(3).primary_key("name").durability("soft");
+    assert_eq!(conn.table_create("person", table_options).ok().map(|created| created.tables_created), Some(1));
+    assert_eq!(queries.recv().ok().map(|query: Value| query.to_string()), Some(r#"[1,[60,["person"],{"durability":"soft","primary_key":"name","replicas":3,"shards":2}]]"#.to_owned()));
+
+    // Whatever isn't set is left out.
+    assert!(conn.table_create("post", TableOptions::default()).is_ok());
+    assert_eq!(queries.recv().ok().map(|query: Value| query.to_string()), Some(r#"[1,[60,["post"]]]"#.to_owned()));
+}
+
+#[test]
+fn test_index_status() {
+    use reql::r::r;
+    use reql::results::IndexStatus;
+    use reql::tree::IntoTree;
+    use serde_json;
+
+    let statuses: Vec<IndexStatus> = serde_json::from_str(r#"[{
+        "index": "author", "ready": false, "progress": 0.25, "multi": false, "geo": false, "outdated": false,
+        "function": {"$reql_type$": "BINARY", "data": "AQID"},
+        "query": "indexCreate('author', function(var1) { return r.row('author'); })"
+    }]"#).unwrap();
+    assert!(!statuses[0].ready);
+    assert_eq!(statuses[0].progress, Some(0.25));
+
+    // What V0_3 and V0_4 servers send.
+    let statuses: Vec<IndexStatus> = serde_json::from_str(r#"[{
+        "index": "author", "ready": true, "multi": false, "geo": false,
+        "function": {"$reql_type$": "BINARY", "data": "AQID"}
+    }]"#).unwrap();
+    assert!(!statuses[0].outdated);
+    assert_eq!(statuses[0].query, None);
+
+    // The function of one index's status is enough to create it again.
+    let copy = r.table("posts").index_create_from("author", statuses[0].function.clone());
+    assert_eq!(serde_json::to_string(&copy.into_tree().ok().unwrap()).unwrap(), r#"[75,[[15,["posts"]],"author",{"$reql_type$":"BINARY","data":"AQID"}]]"#);
+}
+
+#[test]
+fn test_write_result() {
+    use error::Error;
+    use reql::results::WriteResult;
+    use serde_json::{self, Value};
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct User {
+        id: String,
+        name: String,
+    }
+
+    let json: Value = serde_json::from_str(r#"{
+        "inserted": 1, "replaced": 0, "unchanged": 0, "skipped": 0, "deleted": 0, "errors": 1,
+        "first_error": "Duplicate primary key `id`", "generated_keys": ["9f1c0b6e-6f6a-4c47-9a0b-2d6e0c2a9e11"],
+        "changes": [{"old_val": null, "new_val": {"id": "9f1c0b6e-6f6a-4c47-9a0b-2d6e0c2a9e11", "name": "Ada"}}]
+    }"#).unwrap();
+    let result: WriteResult<User> = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!(result.generated_keys.len(), 1);
+    assert_eq!(result.changes[0].new_val.as_ref().map(|user| &user.name[..]), Some("Ada"));
+
+    // A partial failure doesn't pass the check, but what did get written is still there.
+    match WriteResult::<User>::checked(json) {
+        Err(Error::WriteError(ref result)) => {
+            assert_eq!((result.inserted, result.errors), (1, 1));
+            assert_eq!(result.first_error.as_ref().map(String::as_str), Some("Duplicate primary key `id`"));
+            assert_eq!(result.generated_keys, vec!["9f1c0b6e-6f6a-4c47-9a0b-2d6e0c2a9e11".to_owned()]);
+            assert_eq!(result.changes[0].new_val.as_ref().map(|user| &user["name"]), Some(&Value::from("Ada")));
+        },
+        _ => assert!(false),
+    }
+
+    let result: WriteResult = serde_json::from_str(r#"{"deleted": 3, "errors": 0, "inserted": 0, "replaced": 0, "skipped": 0, "unchanged": 0}"#).unwrap();
+    assert_eq!(result.check().ok().map(|result| result.deleted), Some(3));
+}
+
+#[test]
+fn test_pseudo_types() {
+    use reql::types::{self, Binary, Geometry, Group, Grouped, Point, Time};
+    use serde_json::{self, Value};
+
+    let time = Time::new(1514764800.5, 3600);
+    let json = serde_json::to_string(&time).unwrap();
+    assert_eq!(json, r#"{"$reql_type$":"TIME","epoch_time":1514764800.5,"timezone":"+01:00"}"#);
+    assert_eq!(serde_json::from_str::<Time>(&json).unwrap(), time);
+    assert_eq!(Time::new(0.0, -27000).timezone(), "-07:30");
+    assert!(serde_json::from_str::<Time>(r#"{"$reql_type$":"TIME","epoch_time":0,"timezone":"+0é:0"}"#).is_err());
+
+    let binary = Binary(b"hello".to_vec());
+    let json = serde_json::to_string(&binary).unwrap();
+    assert_eq!(json, r#"{"$reql_type$":"BINARY","data":"aGVsbG8="}"#);
+    assert_eq!(serde_json::from_str::<Binary>(&json).unwrap(), binary);
+
+    let line = Geometry::Line(vec![Point::new(-122.4, 37.8), Point::new(-122.3, 37.7)]);
+    let json = serde_json::to_string(&line).unwrap();
+    assert_eq!(json, r#"{"$reql_type$":"GEOMETRY","type":"LineString","coordinates":[[-122.4,37.8],[-122.3,37.7]]}"#);
+    assert_eq!(serde_json::from_str::<Geometry>(&json).unwrap(), line);
+    assert!(serde_json::from_str::<Time>(&json).is_err());
+
+    let raw: Value = serde_json::from_str(r#"{"$reql_type$": "GROUPED_DATA", "data": [["a", 1], ["b", 2]]}"#).unwrap();
+    let expected = Grouped(vec![
+        Group { group: "a".to_owned(), reduction: 1 },
+        Group { group: "b".to_owned(), reduction: 2 },
+    ]);
+    let native = types::native_groups(raw.clone());
+    assert_eq!(native.to_string(), r#"[{"group":"a","reduction":1},{"group":"b","reduction":2}]"#);
+    assert_eq!(serde_json::from_value::<Grouped<String, u32>>(native).unwrap(), expected);
+    assert_eq!(serde_json::from_value::<Grouped<String, u32>>(raw).unwrap(), expected);
+    assert_eq!(Vec::from(expected), vec![("a".to_owned(), 1), ("b".to_owned(), 2)]);
+}
+
+#[test]
+fn test_datum_encoding() {
+    use reql::r::r;
+    use reql::tree::{IntoTree, Tree};
+    use reql::types::Literal;
+    use serde_json::{self, Value};
+    use std::collections::BTreeMap;
+
+    // Arrays inside objects and arrays are wrapped too, or the server would take them for queries.
+    let nested: Value = serde_json::from_str(r#"{"tags": ["a", ["b"]], "n": 1}"#).unwrap();
+    assert_eq!(serde_json::to_string(&Tree::Datum(nested)).unwrap(), r#"{"n":1,"tags":[2,["a",[2,["b"]]]]}"#);
+
+    #[derive(Serialize)]
+    struct Patch {
+        settings: Literal<Value>,
+        old: Literal<Value>,
+    }
+    let patch = r.table("users").update(Patch { settings: Literal(Some(Value::from(vec![1]))), old: Literal(None) }).ok().unwrap();
+    assert_eq!(serde_json::to_string(&patch.into_tree().ok().unwrap()).unwrap(), r#"[53,[[15,["users"]],{"old":[137],"settings":[137,[[2,[1]]]]}]]"#);
+
+    // An object holding a query has to be built with MAKE_OBJ.
+    let mut fields = BTreeMap::new();
+    fields.insert("empty".to_owned(), r.table("users").is_empty());
+    fields.insert("tags".to_owned(), r.expr(vec!["a"]));
+    assert_eq!(serde_json::to_string(&r.expr(fields).into_tree().ok().unwrap()).unwrap(), r#"[3,[],{"empty":[86,[[15,["users"]]]],"tags":[2,["a"]]}]"#);
+}
+
+/// Reads a datum back the way the server does, refusing anything that would be taken for a query.
+#[cfg(test)]
+fn decode_datum(json: &::serde_json::Value) -> Option<::serde_json::Value> {
+    use serde_json::Value;
+
+    match json {
+        &Value::Array(ref term) => match (term.get(0).and_then(Value::as_u64), term.get(1), term.len()) {
+            (Some(2), Some(&Value::Array(ref elements)), 2) => elements.iter().map(decode_datum).collect::<Option<Vec<Value>>>().map(Value::Array),
+            _ => None,
+        },
+        &Value::Object(ref object) => object.iter()
+            .map(|(key, value)| decode_datum(value).map(|value| (key.clone(), value)))
+            .collect::<Option<_>>()
+            .map(Value::Object),
+        json => Some(json.clone()),
+    }
+}
+
+#[cfg(test)]
+fn arbitrary_json() -> ::proptest::strategy::BoxedStrategy<::serde_json::Value> {
+    use proptest::prelude::*;
+    use serde_json::Value;
+
+    let leaf = prop_oneof![
+        Just(Value::Null),
+        any::<bool>().prop_map(Value::from),
+        any::<i64>().prop_map(Value::from),
+        any::<f64>().prop_filter("JSON numbers are finite", |n| n.is_finite()).prop_map(Value::from),
+        ".*".prop_map(Value::from),
+    ];
+
+    leaf.prop_recursive(4, 64, 8, |inner| prop_oneof![
+        prop::collection::vec(inner.clone(), 0..8).prop_map(Value::Array),
+        prop::collection::btree_map(".*", inner, 0..8).prop_map(|object| Value::Object(object.into_iter().collect())),
+    ]).boxed()
+}
+
+#[cfg(test)]
+proptest! {
+    #[test]
+    fn test_datum_round_trip(json in arbitrary_json()) {
+        use reql::tree::Tree;
+        use serde_json;
+
+        let encoded = serde_json::to_value(&Tree::Datum(json.clone())).unwrap();
+        prop_assert_eq!(decode_datum(&encoded), Some(json));
+    }
+}
+
+#[test]
+fn test_take_frame() {
+    use protocol;
+
+    // Two responses, for tokens 1 and 2, with the second one cut short.
+    let mut buffer = vec![1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, b'{', b'}'];
+    buffer.extend_from_slice(&[2, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, b'{']);
+
+    assert_eq!(protocol::take_frame(&mut buffer), Some((1, b"{}".to_vec())));
+    assert_eq!(protocol::take_frame(&mut buffer), None);
+    buffer.push(b'}');
+    assert_eq!(protocol::take_frame(&mut buffer), Some((2, b"{}".to_vec())));
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn test_legacy_handshake() {
+    use error::Error;
+    use protocol;
+    use ql2::VersionDummy_Version;
+
+    let message = protocol::legacy_handshake_message(VersionDummy_Version::V0_4, "key").ok().unwrap();
+    assert_eq!(message, vec![0x20, 0x2d, 0x0c, 0x40, 3, 0, 0, 0, b'k', b'e', b'y', 0xc7, 0x70, 0x69, 0x7e]);
+
+    assert!(protocol::parse_legacy_response("SUCCESS").is_ok());
+    match protocol::parse_legacy_response("ERROR: Incorrect authorization key.\n") {
+        Err(Error::ReqlAuthError) => {},
+        _ => assert!(false),
+    }
+    match protocol::parse_protocol_response("ERROR: Received an unsupported protocol version. This port is for RethinkDB queries.\n") {
+        Err(Error::UnsupportedVersion(_)) => {},
+        _ => assert!(false),
+    }
+}
+
+#[test]
+fn test_backoff() {
+    use backoff::Backoff;
+    use std::time::Duration;
+
+    let backoff = Backoff::new()
+        .initial_delay(Duration::from_millis(100))
+        .max_delay(Duration::from_millis(300))
+        .max_retries(Some(4));
+    let delays = backoff.delays().collect::<Vec<Duration>>();
+    assert_eq!(delays, vec![
+        Duration::from_millis(100),
+        Duration::from_millis(200),
+        Duration::from_millis(300),
+        Duration::from_millis(300),
+    ]);
+}
+
+#[test]
+fn test_read_only() {
+    use reql::tree::Tree;
+    use ql2::Term_TermType;
+    use serde_json::Value;
+
+    let table = Tree::query(Term_TermType::TABLE, vec![Tree::Datum(Value::from("users"))]);
+    let count = Tree::query(Term_TermType::COUNT, vec![table]);
+    assert!(count.is_read_only());
+
+    let delete = Tree::query(Term_TermType::DELETE, vec![count]);
+    assert!(!delete.is_read_only());
+}
+
+#[test]
+fn test_builder() {
+    use error::Error;
+    use reql::r::r;
+    use reql::tree::IntoTree;
+    use serde_json;
+
+    let query = r.db("app").table("users").filter_by(r.row().get_field("age").gt(18)).limit(10);
+    let tree = query.into_tree().ok().unwrap();
+    assert_eq!(serde_json::to_string(&tree).unwrap(), r#"[71,[[39,[[15,[[14,["app"]],"users"]],[69,[[2,[1]],[21,[[31,[[13],"age"]],18]]]]]],10]]"#);
+
+    // BRANCH needs a test and two values, but the error only shows up once the query is run.
+    match r.branch::<&str>(vec![]).limit(1).into_tree() {
+        Err(Error::ReqlDriverCompileError(_)) => {},
+        _ => assert!(false),
+    }
+    assert!(r.branch(vec![r.args(vec![true, false, true])]).into_tree().is_ok());
+}
+
+#[test]
+fn test_selections() {
+    use error::Error;
+    use reql::r::r;
+    use reql::term::Term;
+    use reql::tree::{IntoTree, SelectionType};
+    use serde_json;
+
+    let range = r.table("users").between(r.minval(), 100).index("age").right_bound("closed");
+    assert_eq!(serde_json::to_string(&range.as_tree()).unwrap(), r#"[182,[[15,["users"]],[180],100],{"index":"age","right_bound":"closed"}]"#);
+    assert_eq!(range.as_tree().selection_type(), SelectionType::Selection);
+    assert_eq!(r.table("users").get("ada").as_tree().selection_type(), SelectionType::SingleSelection);
+    assert_eq!(r.table("users").get_all(vec!["ada"]).limit(1).nth(0).as_tree().selection_type(), SelectionType::SingleSelection);
+    assert_eq!(r.table("users").get_all(vec!["ada"]).get_field("name").as_tree().selection_type(), SelectionType::Other);
+
+    // GET_ALL needs at least one key, but the error only shows up once the query is run.
+    match r.table("users").get_all::<&str>(vec![]).into_tree() {
+        Err(Error::ReqlDriverCompileError(_)) => {},
+        _ => assert!(false),
+    }
+    assert!(r.table("users").get_all(vec![r.args(vec!["a", "b"])]).into_tree().is_ok());
+
+    // Writes are only accepted where the server takes them, and turned down as soon as they're
+    // chained anywhere else.
+    assert!(r.table("users").get("ada").delete().ok().unwrap().into_tree().is_ok());
+    let archive = r.table("users").for_each(|user| r.table("archive").get(user.get_field("id")).delete());
+    assert_eq!(serde_json::to_string(&archive.into_tree().ok().unwrap()).unwrap(), r#"[68,[[15,["users"]],[69,[[2,[1]],[54,[[16,[[15,["archive"]],[31,[[10,[1]],"id"]]]]]]]]]]"#);
+    for write in vec![r.table("users").get_all(vec!["ada"]).insert(vec![1]), r.expr(vec![1]).delete(), r.table("users").get_field("name").update(vec![1])] {
+        match write {
+            Err(Error::ReqlDriverCompileError(_)) => {},
+            _ => assert!(false),
+        }
+    }
+
+    // Returned from a function, a bad write is reported when the query is run.
+    match r.table("users").for_each(|user: Term| user.get_field("id").delete()).into_tree() {
+        Err(Error::ReqlDriverCompileError(_)) => {},
+        _ => assert!(false),
+    }
+}
+
+#[test]
+fn test_joins() {
+    use connection::ConnectOptions;
+    use reql::r::r;
+    use reql::results::Joined;
+    use reql::tree::IntoTree;
+    use serde_json::{self, Value};
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Player {
+        name: String,
+        team: String,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Team {
+        id: String,
+    }
+
+    let join = r.table("players").eq_join("team", r.table("teams")).index("id").ordered(true).zip();
+    assert_eq!(serde_json::to_string(&join.into_tree().ok().unwrap()).unwrap(), r#"[72,[[50,[[15,["players"]],"team",[15,["teams"]]],{"index":"id","ordered":true}]]]"#);
+
+    // Elements of an outer join without a match have no right side.
+    let joined: Vec<Joined<Player, Option<Team>>> = serde_json::from_str(r#"[
+        {"left": {"name": "ada", "team": "red"}, "right": {"id": "red"}},
+        {"left": {"name": "bob", "team": "blue"}}
+    ]"#).unwrap();
+    let pairs: Vec<(Player, Option<Team>)> = joined.into_iter().map(Into::into).collect();
+    assert_eq!(pairs[0].1, Some(Team { id: "red".to_owned() }));
+    assert_eq!(pairs[1], (Player { name: "bob".to_owned(), team: "blue".to_owned() }, None));
+
+    // Join results also decode straight into tuples, as a whole or an element at a time.
+    let joined: Value = serde_json::from_str(r#"[
+        {"left": {"name": "ada", "team": "red"}, "right": {"id": "red"}},
+        {"left": {"name": "bob", "team": "blue"}}
+    ]"#).unwrap();
+    let options = ConnectOptions::new("localhost", 28015);
+    let pairs = options.decode::<Vec<(Player, Option<Team>)>>(joined.clone()).ok().unwrap();
+    assert_eq!(pairs[0], (Player { name: "ada".to_owned(), team: "red".to_owned() }, Some(Team { id: "red".to_owned() })));
+    assert_eq!(pairs[1], (Player { name: "bob".to_owned(), team: "blue".to_owned() }, None));
+    let pair = options.decode::<(Player, Option<Team>)>(joined[1].clone()).ok().unwrap();
+    assert_eq!(pair, (Player { name: "bob".to_owned(), team: "blue".to_owned() }, None));
+    assert_eq!(options.decode::<Vec<Joined<Player, Option<Team>>>>(joined).ok().unwrap().len(), 2);
+}
+
+#[test]
+fn test_changes() {
+    use reql::response::{FeedType, Response};
+    use reql::results::{Change, ChangeType, FeedState};
+    use serde_json;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Score {
+        id: String,
+        points: u32,
+    }
+
+    let json = serde_json::from_str(r#"{"t": 3, "r": [{"state": "initializing", "type": "state"}], "n": [3, 5]}"#).unwrap();
+    let response = Response::from_json(json).ok().unwrap();
+    assert_eq!(response.feed_type(), Some(FeedType::OrderByLimit));
+    assert!(response.includes_states());
+
+    let events: Vec<Change<Score>> = serde_json::from_value(response.into_value().ok().unwrap()).unwrap();
+    assert_eq!(events[0].state, Some(FeedState::Initializing));
+    assert!(events[0].new_val.is_none());
+
+    let change: Change<Score> = serde_json::from_str(r#"{
+        "old_val": {"id": "ada", "points": 3}, "new_val": {"id": "ada", "points": 5},
+        "old_offset": 1, "new_offset": 0, "type": "change"
+    }"#).unwrap();
+    assert_eq!(change.change_type, Some(ChangeType::Change));
+    assert_eq!(change.new_val.map(|score| score.points), Some(5));
+    assert_eq!(change.new_offset, Some(0));
+}
+
+/// Sends each query it gets to `queries`.  The first client gets a changefeed with one change and
+/// is hung up on when it asks for more; the ones after it get one last change.
+#[cfg(test)]
+fn serve_feed(queries: ::std::sync::mpsc::Sender<::serde_json::Value>) -> impl Fn(Stub<::std::net::TcpStream>) + Send + 'static {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let accepted = AtomicUsize::new(0);
+    move |mut stub| {
+        let (token, query) = stub.query().unwrap();
+        queries.send(query).unwrap();
+        if accepted.fetch_add(1, Ordering::SeqCst) == 0 {
+            stub.respond(token, r#"{"t":3,"r":[{"new_val":1}],"n":[1]}"#);
+            stub.query();
+        } else {
+            stub.respond(token, r#"{"t":2,"r":[{"new_val":2}]}"#);
+            while stub.query().is_some() {}
+        }
+    }
+}
+
+#[test]
+fn test_feed_resync() {
+    use backoff::Backoff;
+    use feed::FeedEvent;
+    use reql::r::r;
+    use reql::response::FeedType;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let (sender, queries) = mpsc::channel();
+    let options = stub_server(2, serve_feed(sender))
+        .reconnect(Backoff::default().initial_delay(Duration::from_millis(10)).max_retries(Some(1)));
+    let conn = Connection::connect_with(options).ok().unwrap();
+    let mut feed = conn.run_feed::<u32, _>(r.table("scores").changes()).ok().unwrap();
+    assert_eq!(feed.feed_type(), Some(FeedType::Sequence));
+
+    match feed.next() {
+        Some(Ok(FeedEvent::Change(change))) => assert_eq!(change.new_val, Some(1)),
+        _ => assert!(false),
+    }
+
+    // The cursor fails with the connection, and the same query starts the feed over on a new one.
+    match feed.next() {
+        Some(Ok(FeedEvent::Resync)) => {},
+        _ => assert!(false),
+    }
+    let first = queries.recv().unwrap();
+    assert_eq!(queries.recv().unwrap(), first);
+
+    match feed.next() {
+        Some(Ok(FeedEvent::Change(change))) => assert_eq!(change.new_val, Some(2)),
+        _ => assert!(false),
+    }
+    assert!(feed.next().is_none());
+}
+
+#[test]
+fn test_feed_gives_up() {
+    use backoff::Backoff;
+    use error::Error;
+    use feed::FeedEvent;
+    use reql::r::r;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    // Nothing answers once the first connection is gone.
+    let (sender, _queries) = mpsc::channel();
+    let options = stub_server(1, serve_feed(sender))
+        .reconnect(Backoff::default().initial_delay(Duration::from_millis(10)).max_retries(Some(1)));
+    let conn = Connection::connect_with(options).ok().unwrap();
+    let mut feed = conn.run_feed::<u32, _>(r.table("scores").changes()).ok().unwrap();
+
+    match feed.next() {
+        Some(Ok(FeedEvent::Change(change))) => assert_eq!(change.new_val, Some(1)),
+        _ => assert!(false),
+    }
+    match feed.next() {
+        Some(Err(Error::ConnectionLost(_))) => {},
+        _ => assert!(false),
+    }
+    assert!(feed.next().is_none());
+}
+
+#[test]
+fn test_optargs() {
+    use reql::r::r;
+    use reql::tree::IntoTree;
+    use serde_json;
+
+    #[derive(Serialize)]
+    struct Person {
+        name: &'static str,
+    }
+
+    let create = r.db("test").table_create("person").replicas(1).shards(2);
+    assert_eq!(serde_json::to_string(&create.into_tree().ok().unwrap()).unwrap(), r#"[60,[[14,["test"]],"person"],{"replicas":1,"shards":2}]"#);
+
+    let table = r.db("test").table("person").read_mode("outdated");
+    assert_eq!(serde_json::to_string(&table.into_tree().ok().unwrap()).unwrap(), r#"[15,[[14,["test"]],"person"],{"read_mode":"outdated"}]"#);
+
+    let insert = r.db("test").table("person").insert(Person { name: "Nacho" }).ok().unwrap().conflict("update");
+    assert_eq!(serde_json::to_string(&insert.into_tree().ok().unwrap()).unwrap(), r#"[56,[[15,[[14,["test"]],"person"]],{"name":"Nacho"}],{"conflict":"update"}]"#);
+
+    // Optargs on terms without arguments still need the (empty) argument array.
+    assert_eq!(serde_json::to_string(&r.table_list().optarg("x", 1).into_tree().ok().unwrap()).unwrap(), r#"[62,[],{"x":1}]"#);
+}
+
+#[test]
+fn test_documents() {
+    use reql::r::r;
+    use reql::tree::IntoTree;
+    use reql::types::Field;
+    use serde_json;
+
+    // Nested selectors are plain objects, with their arrays made with MAKE_ARRAY.
+    let fields = vec![Field::from("name"), Field::nested("address", vec![Field::from("city"), Field::nested("geo", vec!["lat"])])];
+    let pluck = r.table("users").pluck(fields);
+    assert_eq!(serde_json::to_string(&pluck.into_tree().ok().unwrap()).unwrap(), r#"[33,[[15,["users"]],"name",{"address":[2,["city",{"geo":[2,["lat"]]}]]}]]"#);
+
+    let tags = r.table("posts").get(1).get_field("tags").set_insert("rust").difference(vec!["draft"]);
+    assert_eq!(serde_json::to_string(&tags.into_tree().ok().unwrap()).unwrap(), r#"[95,[[88,[[31,[[16,[[15,["posts"]],1]],"tags"]],"rust"]],[2,["draft"]]]]"#);
+}
+
+#[test]
+fn test_lambdas() {
+    use reql::r::r;
+    use reql::tree::IntoTree;
+    use serde_json;
+
+    let filter = r.table("users").filter(|doc| doc.get_field("age").gt(18));
+    assert_eq!(serde_json::to_string(&filter.into_tree().ok().unwrap()).unwrap(), r#"[39,[[15,["users"]],[69,[[2,[1]],[21,[[31,[[10,[1]],"age"]],18]]]]]]"#);
+
+    // Nested functions get their own variables, and the outer one is still in scope.
+    let nested = r.table("users").for_each(|user| r.table("posts").filter(move |post| post.get_field("author").eq(user)));
+    assert_eq!(serde_json::to_string(&nested.into_tree().ok().unwrap()).unwrap(), r#"[68,[[15,["users"]],[69,[[2,[1]],[39,[[15,["posts"]],[69,[[2,[2]],[17,[[31,[[10,[2]],"author"]],[10,[1]]]]]]]]]]]]"#);
+
+    let sum = r.expr(vec![1, 2, 3]).reduce(|a, b| a.add(b));
+    assert_eq!(serde_json::to_string(&sum.into_tree().ok().unwrap()).unwrap(), r#"[37,[[2,[1,2,3]],[69,[[2,[1,2]],[24,[[10,[1]],[10,[2]]]]]]]]"#);
+
+    // `r.row()` is wrapped in a function of one argument.
+    let row = r.table("users").filter_by(r.row().get_field("age").gt(18));
+    assert_eq!(serde_json::to_string(&row.into_tree().ok().unwrap()).unwrap(), r#"[39,[[15,["users"]],[69,[[2,[1]],[21,[[31,[[13],"age"]],18]]]]]]"#);
+}
+
+#[test]
+fn test_aggregations() {
+    use error::Error;
+    use reql::r::r;
+    use reql::term::Term;
+    use reql::tree::IntoTree;
+    use reql::types::{Format, Group, Grouped};
+    use serde_json::{self, Value};
+
+    // Aggregations take a field name as it is, and a closure as a function.
+    let totals = r.table("orders").group(vec!["customer"]).sum_by("total").ungroup();
+    assert_eq!(serde_json::to_string(&totals.into_tree().ok().unwrap()).unwrap(), r#"[150,[[145,[[144,[[15,["orders"]],"customer"]],"total"]]]]"#);
+    let adults = r.table("users").count_by(|user: Term| user.get_field("age").ge(18));
+    assert_eq!(serde_json::to_string(&adults.into_tree().ok().unwrap()).unwrap(), r#"[43,[[15,["users"]],[69,[[2,[1]],[22,[[31,[[10,[1]],"age"]],18]]]]]]"#);
+
+    // `r.asc`/`r.desc` wrap their own key, and `order_by` takes them as they are.
+    let oldest = r.table("users").order_by(vec![r.desc(r.row().get_field("age")), r.asc("name")]);
+    assert_eq!(serde_json::to_string(&oldest.into_tree().ok().unwrap()).unwrap(), r#"[41,[[15,["users"]],[74,[[69,[[2,[1]],[31,[[13],"age"]]]]]],[73,["name"]]]]"#);
+
+    // With the default format, groups decode into `Grouped` and into a list of pairs.
+    let grouped: Value = serde_json::from_str(r#"{"$reql_type$": "GROUPED_DATA", "data": [["ada", 30], ["bob", 12]]}"#).unwrap();
+    let options = ConnectOptions::new("localhost", 28015);
+    assert_eq!(options.decode::<Grouped<String, u32>>(grouped.clone()).ok().unwrap(), Grouped(vec![
+        Group { group: "ada".to_owned(), reduction: 30 },
+        Group { group: "bob".to_owned(), reduction: 12 },
+    ]));
+    assert_eq!(options.decode::<Vec<(String, u32)>>(grouped.clone()).ok().unwrap(), vec![("ada".to_owned(), 30), ("bob".to_owned(), 12)]);
+    assert_eq!(options.decode::<Value>(grouped.clone()).ok().unwrap().to_string(), r#"[{"group":"ada","reduction":30},{"group":"bob","reduction":12}]"#);
+    match options.decode::<Vec<(String, String)>>(grouped.clone()) {
+        Err(Error::DecodeError(_)) => {},
+        _ => assert!(false),
+    }
+
+    let raw = ConnectOptions::new("localhost", 28015).group_format(Format::Raw);
+    assert_eq!(raw.decode::<Value>(grouped.clone()).ok().unwrap(), grouped);
+    assert_eq!(raw.decode::<Grouped<String, u32>>(grouped).ok().unwrap().into_pairs(), vec![("ada".to_owned(), 30), ("bob".to_owned(), 12)]);
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn test_connect_tls() {
+    use native_tls::{Identity, TlsAcceptor};
+    use protocol::HandshakeVersion;
+    use reql::r::r;
+    use std::net::TcpListener;
+    use std::thread;
+    use tls::TlsOptions;
+
+    // A self-signed certificate for `localhost`.
+    let certificate = include_bytes!("../fixtures/localhost.pem");
+    let key = include_bytes!("../fixtures/localhost.key");
+
+    let acceptor = TlsAcceptor::new(Identity::from_pkcs8(certificate, key).unwrap()).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut stub = Stub::accept(acceptor.accept(stream).unwrap());
+        while let Some((token, query)) = stub.query() {
+            stub.respond(token, &format!(r#"{{"t":1,"r":[{}]}}"#, query[1][1][0]));
+        }
+    });
+
+    // The certificate is only good for `localhost`, not the address connected to.
+    let tls = TlsOptions::new().ca_certificates(certificate).server_name("localhost");
+    let options = ConnectOptions::new("127.0.0.1", port).handshake(HandshakeVersion::V0_4).tls(tls);
+    let conn = Connection::connect_with(options).ok().unwrap();
+    assert_eq!(conn.run::<String, _>(r.table("users")).ok(), Some("users".to_owned()));
+    assert_eq!(conn.run::<String, _>(r.table("posts")).ok(), Some("posts".to_owned()));
+}
+
 // // socat  -v -x TCP4-LISTEN:7888,fork,reuseaddr TCP4:localhost:28015
 // #[test]
 // fn test_create() {
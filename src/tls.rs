@@ -0,0 +1,77 @@
+use error::Error;
+use native_tls::{Certificate, Identity, TlsConnector, TlsStream};
+use std::net::TcpStream;
+
+/// How to set up TLS for servers started with `--driver-tls`.
+///
+/// By default the server's certificate is checked against the system's roots, and against the host
+/// name that was connected to.
+#[derive(Clone)]
+pub struct TlsOptions {
+    ca_certificates: Option<Vec<u8>>,
+    client_certificate: Option<(Vec<u8>, Vec<u8>)>,
+    server_name: Option<String>,
+    verify: bool,
+}
+
+impl TlsOptions {
+    pub fn new() -> TlsOptions {
+        TlsOptions {
+            ca_certificates: None,
+            client_certificate: None,
+            server_name: None,
+            verify: true,
+        }
+    }
+
+    /// PEM-encoded certificates to trust on top of the system's roots, e.g. the CA bundle the
+    /// server's certificate was signed with.
+    pub fn ca_certificates(mut self, pem: &[u8]) -> TlsOptions {
+        self.ca_certificates = Some(pem.to_owned());
+        self
+    }
+
+    /// A PEM-encoded certificate and PKCS #8 key to present to servers that require client
+    /// certificates.
+    pub fn client_certificate(mut self, certificate_pem: &[u8], key_pem: &[u8]) -> TlsOptions {
+        self.client_certificate = Some((certificate_pem.to_owned(), key_pem.to_owned()));
+        self
+    }
+
+    /// The name sent for SNI and checked against the server's certificate, if it isn't the host
+    /// that was connected to (e.g. when connecting by IP address).
+    pub fn server_name(mut self, server_name: &str) -> TlsOptions {
+        self.server_name = Some(server_name.to_owned());
+        self
+    }
+
+    /// Accepts any certificate for any host name.  This throws away everything TLS protects
+    /// against besides eavesdropping, so only use it in development.
+    pub fn danger_skip_verification(mut self) -> TlsOptions {
+        self.verify = false;
+        self
+    }
+
+    /// Runs the TLS handshake over an open socket to `host`.
+    pub fn connect(&self, host: &str, stream: TcpStream) -> Result<TlsStream<TcpStream>, Error> {
+        let mut builder = TlsConnector::builder();
+        if let Some(ref pem) = self.ca_certificates {
+            for certificate in my_try!(Certificate::stack_from_pem(pem)) {
+                builder.add_root_certificate(certificate);
+            }
+        }
+        if let Some((ref certificate, ref key)) = self.client_certificate {
+            builder.identity(my_try!(Identity::from_pkcs8(certificate, key)));
+        }
+        builder.danger_accept_invalid_certs(!self.verify);
+        builder.danger_accept_invalid_hostnames(!self.verify);
+        let connector = my_try!(builder.build());
+
+        let server_name = match self.server_name {
+            Some(ref server_name) => server_name.as_str(),
+            None => host,
+        };
+
+        Ok(my_try!(connector.connect(server_name, stream)))
+    }
+}
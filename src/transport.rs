@@ -0,0 +1,72 @@
+#[cfg(feature = "tls")]
+use native_tls::TlsStream;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// The byte stream a connection talks over: plain TCP, or TLS on top of it.  The handshake and the
+/// query framing don't care which.
+pub enum Transport {
+    Tcp(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(TlsStream<TcpStream>),
+}
+
+impl Transport {
+    /// The socket underneath, for socket options and shutting down.
+    pub fn socket(&self) -> &TcpStream {
+        match self {
+            &Transport::Tcp(ref stream) => stream,
+            #[cfg(feature = "tls")]
+            &Transport::Tls(ref stream) => stream.get_ref(),
+        }
+    }
+
+    /// The number of bytes that have already been taken off the socket and decrypted, but not read
+    /// yet.  Waiting on the socket won't tell us about these.
+    pub fn buffered(&self) -> usize {
+        match self {
+            &Transport::Tcp(_) => 0,
+            #[cfg(feature = "tls")]
+            &Transport::Tls(ref stream) => stream.buffered_read_size().unwrap_or(0),
+        }
+    }
+
+    /// The read timeout the background reader runs with once the handshake is done.  Reading from a
+    /// TLS stream needs the lock, so those reads have to give up now and then to let writers in.
+    pub fn reader_timeout(&self) -> Option<Duration> {
+        match self {
+            &Transport::Tcp(_) => None,
+            #[cfg(feature = "tls")]
+            &Transport::Tls(_) => Some(Duration::from_millis(100)),
+        }
+    }
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            &mut Transport::Tcp(ref mut stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            &mut Transport::Tls(ref mut stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            &mut Transport::Tcp(ref mut stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            &mut Transport::Tls(ref mut stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            &mut Transport::Tcp(ref mut stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            &mut Transport::Tls(ref mut stream) => stream.flush(),
+        }
+    }
+}